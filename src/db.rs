@@ -1,2 +1,7 @@
 pub mod db_gateway;
 pub mod db_models;
+pub mod db_store;
+pub mod db_migrate;
+pub mod db_mongo;
+pub mod db_sqlite;
+pub mod db_memory;
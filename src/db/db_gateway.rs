@@ -1,86 +1,26 @@
 //  DbGateway
 //  This is a wrapper class which can hook to databases
-//  like MongoDb, MySQL etc.
+//  like MongoDb, SQLite etc. The backend is picked by the config file.
 
 use std::fmt;
-use std::fs::File;
-use bson::{doc, Bson};
-use std::iter::{IntoIterator, Iterator};
-use mongodb::{
-    Client,
-    Database,
-    options::{
-        auth::{
-            Credential,
-            AuthMechanism
-        }, 
-    StreamAddress, 
-    ClientOptions, 
-    FindOneOptions, 
-    FindOneAndUpdateOptions
-    }
-};
-use mongodb::options::IndexModel;
-use super::db_models::{DocPerson, CandidatePersonDb, DbConfig};
+use std::collections::HashSet;
+use super::db_models::{DocPerson, DbConfig, DocExclusion, DocResponse};
+use super::db_store::{PersonStore, Filter, ImportOutcome, IndexReport};
+use super::db_migrate::{Migration, latest_version};
+use super::db_mongo::MongoStore;
+use super::db_sqlite::SqliteStore;
+use super::db_memory::MemoryStore;
 use crate::qdmatch::model::CandidatePerson;
-use serde::Deserialize;
-
-
-
-struct DbIndexModels {
-    iter:Vec<IndexModel>,
-}
-
-struct DbIndexModelIterator {
-    iter:Vec<IndexModel>,
-}
-
-impl Iterator for DbIndexModelIterator {
-
-    type Item = IndexModel;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iter.pop()
-    }
-}
-
-
-impl IntoIterator for DbIndexModels {
-    type Item = IndexModel;
-    type IntoIter = DbIndexModelIterator;
-
-    fn into_iter(self) -> Self::IntoIter {
-        DbIndexModelIterator {
-            iter:self.iter
-        }
-    }
-}
-
-impl DbIndexModels {
-    fn new() -> Self {
-        Self {
-            iter:Vec::new()
-        }
-    }
-
-    fn add(&mut self, node:IndexModel) {
-        self.iter.push(node);
-    }
-}
 
 
 
-
-const DEFAULT_COLLECTION_PERSON:&'static str = "persons";
-
 /// A database wraper which provides neccessary operation specific for the application
 pub struct DbGateway {
     /// Database Settings
     config:DbConfig,
 
-    /// MongoDB database references
-    client:Option<Client>,
-    database:Option<Database>
+    /// Backend picked by the settings, once connected
+    store:Option<Box<dyn PersonStore>>
 }
 
 
@@ -90,6 +30,11 @@ pub enum DbError {
     //NetworkError,
     //QueryError,
     MongoError,
+    SqliteError,
+    StorageError,
+    UnknownBackend(String),
+    ConfigError(String),
+    NotConnected,
     ReconnetRequestError,
     UnwrapError,
     NoPersonFound,
@@ -103,6 +48,18 @@ impl fmt::Display for DbError {
                 write!(f, "No person found !")
             }
 
+            DbError::UnknownBackend(backend) => {
+                write!(f, "Unknown database backend {}, expected mongodb, sqlite or memory", backend)
+            }
+
+            DbError::ConfigError(message) => {
+                write!(f, "Bad database config, {}", message)
+            }
+
+            DbError::NotConnected => {
+                write!(f, "Database is not connected")
+            }
+
             _ => {
                 write!(f, "Database Error Occured")
             }
@@ -111,258 +68,162 @@ impl fmt::Display for DbError {
     }
 }
 
-impl std::convert::From<mongodb::error::Error> for DbError {
-    fn from(m_error:mongodb::error::Error) -> Self {
-        println!("Mongo Error :: {:?}", m_error);
-        DbError::MongoError
-    }
-}
-
 
 impl DbGateway {
 
-    /// Returns a new database, which is ready to connect with specified uri
+    /// Returns a new database, which is ready to connect with the settings of the config file
     /// 
     /// # Argument
     /// 
-    /// * `uri` - Uri of the database server
+    /// * `filename` - Config file, its fields can be overridden by QDATES_DB_* environment variables
     /// 
-    pub fn new(filename:&str) -> Option<Self> {
-
-        //Read policy file and create matcher
-        if let Ok(file) = File::open(filename) {
-            if let Ok(config) = serde_json::from_reader(file) {
-                return Some(DbGateway {
-                    config:config,
-                    client:None,
-                    database:None,
-                });
-            }
-        }
-        
-        //No Config file found
-        None
-
-        
+    pub fn new(filename:&str) -> Result<Self, DbError> {
+        Ok(DbGateway {
+            config:DbConfig::load(filename)?,
+            store:None,
+        })
     }
 
     /// Connect to the datbase, Before performing any datbase related
     /// operation this function needs to be called once and only once at the begining of the program.
     pub fn connect(&mut self) -> Result<(),DbError> {
 
-        //Check if client is already initialized
-        match &self.client {
-            //Client never been initialized.
+        //Check if store is already initialized
+        match &self.store {
+            //Store never been initialized.
             None => {
                 println!("Connecting to database ....");
             }
             
-            //We already have a client.
+            //We already have a store.
             _ => {
                 println!("Database already connected !");
                 return Err(DbError::ReconnetRequestError)
             }
         }
 
-        //Connect to the client
-        let auth_mechnism = AuthMechanism::ScramSha256;
-        let cred = Credential::builder()
-            .username(String::from(&self.config.username))
-            .password(String::from(&self.config.password))
-            .source(Some(String::from(&self.config.source)))
-            .mechanism(Some(auth_mechnism))
-            .build();
+        let store:Box<dyn PersonStore> = match &self.config.backend.to_lowercase()[..] {
+            "mongodb" | "mongo" => Box::new(MongoStore::connect(&self.config)?),
+            "sqlite" => Box::new(SqliteStore::open(self.config.path.as_ref().map(|path| &path[..]).unwrap_or("qdates.db"))?),
+            "memory" => match &self.config.path {
+                Some(path) => Box::new(MemoryStore::open(path)?),
+                None => Box::new(MemoryStore::new())
+            },
+            backend => return Err(DbError::UnknownBackend(String::from(backend)))
+        };
 
-        
+        println!("Database Connected !");
 
-        let port = self.config.port.parse::<u16>();
-        let mut portNo:Option<u16>= None;
-        if let Ok(_port) = port {
-            portNo = Some(_port);
-        }
+        //Move Ownerships and return
+        self.store = Some(store);
 
-        let client_options = ClientOptions::builder()
-            .hosts(vec!{StreamAddress{
-                hostname:String::from(&self.config.host),
-                port:portNo
-            }})
-            .direct_connection(true)
-            .app_name(String::from(&self.config.app))
-            .credential(Some(cred))
-            .build();
-
-        let client = Client::with_options(client_options)?;
-        
-        //Select the database
-        let database = client.database(&self.config.database[..]);
+        //Missing indexes are created right away, conflicting ones wait for a repair
+        match self.ensureIndexes(false) {
+            Ok(reports) => {
+                for report in reports.iter().filter(|report| !report.state.is_ok()) {
+                    println!("Index {}", report);
+                }
+            }
 
-        if let Err(e) = database.list_collections(None, None) {
-            println!("{:?}", e);
-            return Err(DbError::MongoError);
+            Err(e) => {
+                println!("Indexes could not be checked : {}", e);
+            }
         }
 
-        
-        println!("Database Connected !");
+        //Persons are only read and written the latest way
+        match self.schemaVersion() {
+            Ok(version) if version < latest_version() => {
+                println!("Database schema is at version {} of {}, run migrate to update it", version, latest_version());
+            }
+
+            Ok(_) => {}
+
+            Err(e) => {
+                println!("Schema version could not be checked : {}", e);
+            }
+        }
 
-        //Move Ownerships and return
-        self.database = Some(database);
-        self.client = Some(client);
         Ok(())
     }
 
-    /// Get a person from the database by searching through its uiq
-    pub fn getPerson(&mut self, qid:&String) -> Result<Option<DocPerson>, DbError> {
+    fn store(&mut self) -> Result<&mut Box<dyn PersonStore>, DbError> {
+        self.store.as_mut().ok_or(DbError::NotConnected)
+    }
+}
 
-        if let Some(db) = &self.database {
-            //Get Person
-            let filter = doc! { "qid":qid};
-            let collection = db.collection(DEFAULT_COLLECTION_PERSON);
-            let find_options = FindOneOptions::builder()
-                .sort(doc!{"name":1})
-                .build();
-            
-            match collection.find_one(filter, find_options) {
-                Ok(_doc) => {
-                    if let Some(document) = _doc {
-                        match bson::from_bson::<DocPerson>(bson::Bson::Document(document)) {
-                            Ok(person) => {
-                                return Ok(Some(person));
-                            }
-                            Err(e) => {
-                                return Err(DbError::DbParsingError);
-                            }
-                        }
-                        
-                    }else {
-                        return Err(DbError::NoPersonFound);
-                    }
-                }
 
-                Err(e) => {
-                    println!("{:?}", e);
-                }
-                
-            }
-        }
+impl PersonStore for DbGateway {
 
-        Err(DbError::MongoError)
+    fn getPerson(&mut self, qid:&String) -> Result<DocPerson, DbError> {
+        self.store()?.getPerson(qid)
     }
 
+    fn search(&mut self, filter:&Filter) -> Result<Vec<DocPerson>, DbError> {
+        self.store()?.search(filter)
+    }
 
-    pub fn getCandidates(&mut self, filter: impl Into<Option<bson::ordered::OrderedDocument>>) 
-        -> Result<Vec<CandidatePerson>, DbError> {
-            
-        let mut persons:Vec<CandidatePerson> = Vec::new();
-        if let Some(db) = &self.database {
-            let collection = db.collection(DEFAULT_COLLECTION_PERSON);
-            //let mut filter:Document = doc! { "qid":{"$ne":"1"}};
-            let mut cursor = collection.find(filter, None)?;
-            for result in cursor {
-                match result {
-                    Ok(document) => {
-                        match bson::from_bson::<CandidatePersonDb>(bson::Bson::Document(document)) {
-                            Ok(personDb) => {
-                                let person:CandidatePerson = personDb.into();
-                                persons.push(person);
-                            }
-                            Err(e) => {
-                                println!("{:?}", e);
-                            }
-                        }
-                    }
-                    
-                    Err(e) => {
-                        println!("Error while iterating in db cursor");
-                    }
-                }
-            }
+    fn insert(&mut self, person:&DocPerson) -> Result<bool, DbError> {
+        self.store()?.insert(person)
+    }
 
-            return Ok(persons);
-        }
+    fn update(&mut self, person:&DocPerson) -> Result<bool, DbError> {
+        self.store()?.update(person)
+    }
+
+    fn setResponseRating(&mut self, qid:&String, rating:f32) -> Result<(), DbError> {
+        self.store()?.setResponseRating(qid, rating)
+    }
 
-        Err(DbError::MongoError)
+    fn getExclusions(&mut self, qid:Option<&String>) -> Result<Vec<DocExclusion>, DbError> {
+        self.store()?.getExclusions(qid)
     }
 
-    //Update Person
-    pub fn insertAndCheckDuplicate(&mut self, persons:Vec<DocPerson>, checkDuplicate:bool) 
-        -> Result<Vec<DocPerson>, DbError> {
+    fn addExclusion(&mut self, exclusion:&DocExclusion) -> Result<(), DbError> {
+        self.store()?.addExclusion(exclusion)
+    }
 
-        let mut failed_entries:Vec<DocPerson> = Vec::new();
+    fn getResponses(&mut self, qid:&String) -> Result<Vec<DocResponse>, DbError> {
+        self.store()?.getResponses(qid)
+    }
 
-        if let Some(db) = &self.database {
-            
-            for person in persons {
-                //let filter = doc! { "qid": &person.qid };
-                let collection = db.collection(DEFAULT_COLLECTION_PERSON);
-                let filter = doc! { "qid": &person.qid };
-                let updateOption = FindOneAndUpdateOptions::builder()
-                    .upsert(false)
-                    .build();
-                    
-                if checkDuplicate == false {
-                    let doc_to_update = doc! { 
-                        "$set":{
-                            "qid":&person.qid,
-                            "name": &person.name,
-                            "gender":&person.gender,
-                            "age":&person.age.to_string(),
-                            "email":&person.email,
-                            "phone":&person.phone,
-                            "city":&person.city,
-                            "languages":&person.languages,
-                            "education":&person.education,
-                            "response_rating":&person.response_rating.to_string(),
-                            "verbal_ability":&person.verbal_ability,
-                            "seeking":&person.seeking,
-                        }
-                    };
-                    match  collection.find_one_and_update(filter, doc_to_update, updateOption) {
-                        Ok(Some(d)) => {}
-                        Err(_) | Ok(None) => {
-                            failed_entries.push(person);
-                        }
-                        
-                    }
-
-                    continue;
-                }
+    fn insertResponse(&mut self, response:&DocResponse) -> Result<(), DbError> {
+        self.store()?.insertResponse(response)
+    }
 
-                if let Ok(_doc) = collection.find_one(filter, None) {
-                    if let Some(doc) = _doc {
-                        //Entry Already Present
-                        failed_entries.push(person);
-                        
-                    }else{
-                        //No Such Entry, Insert It
-                        let doc_to_insert = doc! { 
-                            "qid": &person.qid, 
-                            "name": &person.name,
-                            "gender":&person.gender,
-                            "age":&person.age.to_string(),
-                            "email":&person.email,
-                            "phone":&person.phone,
-                            "city":&person.city,
-                            "languages":&person.languages,
-                            "education":&person.education,
-                            "response_rating":&person.response_rating.to_string(),
-                            "verbal_ability":&person.verbal_ability,
-                            "seeking":&person.seeking,
-
-                        };
-                        if let Err(_) = collection.insert_one(doc_to_insert, None) {
-                            failed_entries.push(person);
-                        }
-
-                    }
-                }else{
-                    //Problem with the database
-                    failed_entries.push(person);
-                }
-            }
-        }
-        
+    //Backends may answer these better than the defaults
+    fn getCandidates(&mut self, filter:&Filter) -> Result<Vec<CandidatePerson>, DbError> {
+        self.store()?.getCandidates(filter)
+    }
+
+    fn importPersons(&mut self, persons:&[DocPerson], checkDuplicate:bool) -> Result<Vec<ImportOutcome>, DbError> {
+        self.store()?.importPersons(persons, checkDuplicate)
+    }
 
-        Ok(failed_entries)
+    fn schemaVersion(&mut self) -> Result<u32, DbError> {
+        self.store()?.schemaVersion()
     }
 
-}
\ No newline at end of file
+    fn setSchemaVersion(&mut self, version:u32) -> Result<(), DbError> {
+        self.store()?.setSchemaVersion(version)
+    }
+
+    fn applyMigration(&mut self, migration:&Migration, dry_run:bool) -> Result<usize, DbError> {
+        self.store()?.applyMigration(migration, dry_run)
+    }
+
+    fn indexes(&mut self) -> Result<Vec<IndexReport>, DbError> {
+        self.store()?.indexes()
+    }
+
+    fn ensureIndexes(&mut self, rebuild:bool) -> Result<Vec<IndexReport>, DbError> {
+        self.store()?.ensureIndexes(rebuild)
+    }
+
+    fn excludedFor(&mut self, qid:&String) -> Result<HashSet<String>, DbError> {
+        self.store()?.excludedFor(qid)
+    }
+
+    fn distinctValues(&mut self, field:&str) -> Result<Vec<String>, DbError> {
+        self.store()?.distinctValues(field)
+    }
+}
@@ -0,0 +1,188 @@
+//  DbMemory
+//  Storage of the persons in memory, optionally kept in a json snapshot
+//  file between runs. Meant for trying qdates out and for small events.
+
+use std::fs::File;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use super::db_gateway::DbError;
+use super::db_models::{DocPerson, DocExclusion, DocResponse};
+use super::db_store::{PersonStore, Filter, ImportOutcome};
+
+
+/// Everything the store holds, as written to the snapshot
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Snapshot {
+    #[serde(default)]
+    persons:Vec<DocPerson>,
+
+    #[serde(default)]
+    exclusions:Vec<DocExclusion>,
+
+    #[serde(default)]
+    responses:Vec<DocResponse>,
+}
+
+
+pub struct MemoryStore {
+    data:Snapshot,
+
+    /// Snapshot file written after every change, none to forget everything on exit
+    path:Option<String>,
+}
+
+impl MemoryStore {
+    /// Empty store which is not saved
+    pub fn new() -> Self {
+        MemoryStore {
+            data:Snapshot::default(),
+            path:None
+        }
+    }
+
+    /// Store with the content of the snapshot file, empty if the file doesn't exist yet
+    pub fn open(path:&str) -> Result<Self, DbError> {
+        let data = if Path::new(path).exists() {
+            let file = File::open(path).map_err(|_| DbError::StorageError)?;
+            serde_json::from_reader(file).map_err(|_| DbError::DbParsingError)?
+        }else{
+            Snapshot::default()
+        };
+
+        Ok(MemoryStore {
+            data,
+            path:Some(String::from(path))
+        })
+    }
+
+    fn save(&self) -> Result<(), DbError> {
+        if let Some(path) = &self.path {
+            let file = File::create(path).map_err(|_| DbError::StorageError)?;
+            serde_json::to_writer_pretty(file, &self.data).map_err(|_| DbError::StorageError)?;
+        }
+
+        Ok(())
+    }
+
+    fn position(&self, qid:&String) -> Option<usize> {
+        self.data.persons.iter().position(|person| &person.qid == qid)
+    }
+
+    /// Add the person without saving, false if the qid is taken already
+    fn add(&mut self, person:&DocPerson) -> bool {
+        if self.position(&person.qid).is_some() {
+            return false;
+        }
+
+        self.data.persons.push(person.clone());
+        true
+    }
+
+    /// Replace the person without saving, false if the qid is unknown
+    fn replace(&mut self, person:&DocPerson) -> bool {
+        let index = match self.position(&person.qid) {
+            Some(index) => index,
+            None => return false
+        };
+
+        //Rating and birthdate are kept, like the $set of the other backends
+        let stored = &mut self.data.persons[index];
+        let rating = stored.response_rating.clone();
+        let birthdate = person.birthdate.clone().or(stored.birthdate.take());
+        *stored = person.clone();
+        stored.response_rating = rating;
+        stored.birthdate = birthdate;
+        true
+    }
+}
+
+
+impl PersonStore for MemoryStore {
+
+    fn getPerson(&mut self, qid:&String) -> Result<DocPerson, DbError> {
+        match self.position(qid) {
+            Some(index) => Ok(self.data.persons[index].clone()),
+            None => Err(DbError::NoPersonFound)
+        }
+    }
+
+    fn search(&mut self, filter:&Filter) -> Result<Vec<DocPerson>, DbError> {
+        Ok(self.data.persons.iter()
+            .filter(|person| filter.accepts(person))
+            .cloned()
+            .collect())
+    }
+
+    fn insert(&mut self, person:&DocPerson) -> Result<bool, DbError> {
+        let inserted = self.add(person);
+        if inserted {
+            self.save()?;
+        }
+        Ok(inserted)
+    }
+
+    fn update(&mut self, person:&DocPerson) -> Result<bool, DbError> {
+        let updated = self.replace(person);
+        if updated {
+            self.save()?;
+        }
+        Ok(updated)
+    }
+
+    /// The snapshot is written once for all persons
+    fn importPersons(&mut self, persons:&[DocPerson], checkDuplicate:bool) -> Result<Vec<ImportOutcome>, DbError> {
+        let outcomes:Vec<ImportOutcome> = persons.iter()
+            .map(|person| {
+                let stored = if checkDuplicate {
+                    self.add(person)
+                }else{
+                    self.replace(person)
+                };
+                ImportOutcome::of(Ok(stored), checkDuplicate)
+            })
+            .collect();
+        self.save()?;
+
+        Ok(outcomes)
+    }
+
+    fn setResponseRating(&mut self, qid:&String, rating:f32) -> Result<(), DbError> {
+        if let Some(index) = self.position(qid) {
+            self.data.persons[index].response_rating = rating.to_string();
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    fn getExclusions(&mut self, qid:Option<&String>) -> Result<Vec<DocExclusion>, DbError> {
+        Ok(self.data.exclusions.iter()
+            .filter(|exclusion| match qid {
+                Some(qid) => exclusion.involves(qid),
+                None => true
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn addExclusion(&mut self, exclusion:&DocExclusion) -> Result<(), DbError> {
+        if !self.data.exclusions.contains(exclusion) {
+            self.data.exclusions.push(exclusion.clone());
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    fn getResponses(&mut self, qid:&String) -> Result<Vec<DocResponse>, DbError> {
+        Ok(self.data.responses.iter()
+            .filter(|response| &response.qid == qid)
+            .cloned()
+            .collect())
+    }
+
+    fn insertResponse(&mut self, response:&DocResponse) -> Result<(), DbError> {
+        self.data.responses.push(response.clone());
+        self.save()
+    }
+}
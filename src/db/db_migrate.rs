@@ -0,0 +1,44 @@
+//  DbMigrate
+//  Numbered changes to how persons are stored. A database records the
+//  version it is at, `migrate` applies the migrations above it in order.
+
+use std::fmt;
+
+
+pub struct Migration {
+    pub version:u32,
+    pub description:&'static str,
+}
+
+/// Every migration in the order they are applied, versions count up from 1
+pub const MIGRATIONS:&'static [Migration] = &[
+    Migration { version:1, description:"Store age as a number" },
+    Migration { version:2, description:"Store response rating as a number, unrated persons as null" },
+    Migration { version:3, description:"Replace blank placeholders with null" },
+];
+
+/// Text fields which used a blank placeholder before migration 3
+pub const PLACEHOLDER_FIELDS:&'static [&'static str] = &["gender", "email", "phone", "city", "profession", "education", "verbal_ability", "seeking"];
+
+/// Version of a database with every migration applied
+pub fn latest_version() -> u32 {
+    MIGRATIONS.iter().map(|migration| migration.version).max().unwrap_or(0)
+}
+
+
+/// What a migration did, or would do on a dry run
+pub struct MigrationReport {
+    pub version:u32,
+    pub description:&'static str,
+
+    /// Persons changed by the migration
+    pub changed:usize,
+    pub dry_run:bool,
+}
+
+impl fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>3} {:<60} {} persons {}", self.version, self.description, self.changed,
+            if self.dry_run { "to change" } else { "changed" })
+    }
+}
@@ -1,39 +1,214 @@
 use std::fmt;
-use serde::Deserialize;
+use std::env;
+use std::fs::File;
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Deserializer, Serialize};
+use super::db_gateway::DbError;
 use crate::qdmatch::model::CandidatePerson;
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct DocPerson {
     pub qid:String,                 // A unique id in the system
     pub name:String,
+
+    #[serde(deserialize_with = "text")]
     pub gender:String,
-    pub age:String,                     
+
+    #[serde(deserialize_with = "text")]
+    pub age:String,                 // Stored as a number, null when unknown
+
+    #[serde(deserialize_with = "text")]
     pub email:String,               // Primary Mail address
+
+    #[serde(deserialize_with = "text")]
     pub phone:String,               // Phone No. including country code
+
+    #[serde(deserialize_with = "text")]
     pub city:String,                // City the person is living in
+
     pub languages:Vec<String>,       // What language can this person speaks in priority order
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub profession:String,          // What does this person do for living
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub education:String,           // Education of the Person
 
-    #[serde(default = "default_string")]
-    pub response_rating:String,         // How well this person reponds outof 10
+    #[serde(default = "default_string", deserialize_with = "text")]
+    pub response_rating:String,         // How well this person reponds outof 10, blank until rated
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub verbal_ability:String,
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub seeking:String,
 
+    #[serde(default)]
+    pub birthdate:Option<String>,     // YYYY-MM-DD, age is computed from it when given, by the matcher and by queries alike
+
     //So
 }
 
 fn default_string() -> String {
-    " ".to_string()
+    String::new()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextOrNumber {
+    Text(String),
+    Number(f64),
+}
+
+/// Text of a field which may be stored as text, as a number or as null.
+/// Null is blank, like the migrated databases store it
+fn text<'de, D>(deserializer:D) -> Result<String, D::Error>
+    where D:Deserializer<'de> {
+    Ok(match Option::<TextOrNumber>::deserialize(deserializer)? {
+        Some(TextOrNumber::Text(text)) => text,
+        Some(TextOrNumber::Number(number)) => number.to_string(),
+        None => default_string()
+    })
+}
+
+/// Days since 1970-01-01 of a date of the proleptic gregorian calendar
+fn days_from_civil(year:i64, month:i64, day:i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Date `YYYY-MM-DD` of the days since 1970-01-01
+pub fn date_of_days(days:i64) -> String {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Days since 1970-01-01 of a date `YYYY-MM-DD`, None if it isn't one
+fn days_of_date(date:&str) -> Option<i64> {
+    let parts:Vec<i64> = date.trim().split('-')
+        .map(|part| part.trim().parse::<i64>())
+        .collect::<Result<Vec<i64>, _>>()
+        .ok()?;
+    if parts.len() != 3 || parts[1] < 1 || parts[1] > 12 || parts[2] < 1 || parts[2] > 31 {
+        return None;
+    }
+
+    Some(days_from_civil(parts[0], parts[1], parts[2]))
+}
+
+/// Date as stored, `YYYY-MM-DD` with leading zeros, None if it isn't one
+pub fn normalize_date(date:&str) -> Option<String> {
+    days_of_date(date).map(date_of_days)
+}
+
+fn today() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs() as i64 / 86400).unwrap_or_default()
+}
+
+/// Age in years as of today from a birthdate `YYYY-MM-DD`
+pub fn age_from_birthdate(birthdate:&str) -> Option<f32> {
+    let today = today();
+    let born = days_of_date(birthdate)?;
+    if born > today {
+        return None;
+    }
+
+    Some(((today - born) as f64 / 365.2425) as f32)
+}
+
+/// Earliest and latest birthdate of persons aged between `min` and `max` as of today,
+/// a day wider on both ends so the ages of `age_from_birthdate` are all inside
+pub fn birthdates_between(min:f32, max:f32) -> (String, String) {
+    let today = today();
+    let earliest = today - (max as f64 * 365.2425).ceil() as i64 - 1;
+    let latest = today - (min.max(0.0) as f64 * 365.2425).floor() as i64 + 1;
+    (date_of_days(earliest), date_of_days(latest))
+}
+
+/// Age from the birthdate if there is one, from the age field otherwise
+fn age_of(age:&str, birthdate:&Option<String>) -> Option<f32> {
+    if let Some(age) = birthdate.as_ref().and_then(|birthdate| age_from_birthdate(birthdate)) {
+        return Some(age);
+    }
+
+    age.trim().parse::<f32>().ok()
+}
+
+impl DocPerson {
+    /// Value of a profile field by its name in the database
+    pub fn field(&self, name:&str) -> Option<&String> {
+        match name {
+            "qid" => Some(&self.qid),
+            "name" => Some(&self.name),
+            "gender" => Some(&self.gender),
+            "age" => Some(&self.age),
+            "email" => Some(&self.email),
+            "phone" => Some(&self.phone),
+            "city" => Some(&self.city),
+            "profession" => Some(&self.profession),
+            "education" => Some(&self.education),
+            "response_rating" => Some(&self.response_rating),
+            "verbal_ability" | "verbal" => Some(&self.verbal_ability),
+            "seeking" => Some(&self.seeking),
+            _ => None
+        }
+    }
+
+    /// Numeric value of a profile field by its name in the database
+    pub fn field_f32(&self, name:&str) -> Option<f32> {
+        match name {
+            "age" => age_of(&self.age, &self.birthdate),
+            _ => self.field(name)?.trim().parse::<f32>().ok()
+        }
+    }
+
+    /// Response rating out of 10, None if the person is not rated yet
+    pub fn rating(&self) -> Option<f32> {
+        rating_of(&self.response_rating)
+    }
+
+    /// Value of a profile field holding a list by its name in the database
+    pub fn field_list(&self, name:&str) -> Option<&Vec<String>> {
+        match name {
+            "languages" => Some(&self.languages),
+            _ => None
+        }
+    }
+
+    /// Categorical profile field by its name in the database, to normalize it
+    pub fn field_mut(&mut self, name:&str) -> Option<&mut String> {
+        match name {
+            "gender" => Some(&mut self.gender),
+            "city" => Some(&mut self.city),
+            "profession" => Some(&mut self.profession),
+            "education" => Some(&mut self.education),
+            "verbal_ability" | "verbal" => Some(&mut self.verbal_ability),
+            "seeking" => Some(&mut self.seeking),
+            _ => None
+        }
+    }
+
+    pub fn field_list_mut(&mut self, name:&str) -> Option<&mut Vec<String>> {
+        match name {
+            "languages" => Some(&mut self.languages),
+            _ => None
+        }
+    }
 }
 
 impl fmt::Display for DocPerson {
@@ -51,26 +226,44 @@ impl fmt::Display for DocPerson {
 pub struct CandidatePersonDb {
     pub qid:String,
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub name:String,
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub gender:String,
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub age:String,
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub phone:String,
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub email:String,
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub education:String,
 
-    #[serde(default = "default_string")]
+    #[serde(default = "default_string", deserialize_with = "text")]
     pub verbal_ability:String,
+
+    #[serde(default = "default_string", deserialize_with = "text")]
+    pub city:String,
+
+    #[serde(default = "default_string", deserialize_with = "text")]
+    pub profession:String,
+
+    #[serde(default = "default_string", deserialize_with = "text")]
+    pub seeking:String,
+
+    #[serde(default)]
+    pub birthdate:Option<String>,
+
+    #[serde(default)]
+    pub languages:Vec<String>,
+
+    #[serde(default = "default_string", deserialize_with = "text")]
+    pub response_rating:String,
 }
 
 impl Into<CandidatePerson> for CandidatePersonDb {
@@ -78,27 +271,554 @@ impl Into<CandidatePerson> for CandidatePersonDb {
         CandidatePerson {
             qid:self.qid,
             name:self.name,
-            age:self.age.parse::<f32>().unwrap_or_default(),
+            age:age_of(&self.age, &self.birthdate),
+            birthdate:self.birthdate,
             gender:self.gender,
             education:self.education,
             verbal:self.verbal_ability,
+            city:self.city,
+            profession:self.profession,
+            seeking:self.seeking,
+            languages:self.languages,
+            response_rating:rating_of(&self.response_rating),
             phone:self.phone,
             email:self.email,
-            match_score:0.0
+            match_score:0.0,
+            explanation:Vec::new(),
+            their_explanation:Vec::new()
+        }
+    }
+}
+
+
+
+/// Why a person is kept out of the candidates of someone
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExclusionKind {
+    Met,                            // The two persons already met
+    Blocked,                        // One of the two persons blocked the other
+    OptedOut,                       // Person doesn't want to be matched at all
+}
+
+impl ExclusionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExclusionKind::Met => "met",
+            ExclusionKind::Blocked => "blocked",
+            ExclusionKind::OptedOut => "opted_out"
+        }
+    }
+
+    pub fn parse(kind:&str) -> Option<Self> {
+        match &kind.trim().to_lowercase()[..] {
+            "met" => Some(ExclusionKind::Met),
+            "blocked" => Some(ExclusionKind::Blocked),
+            "opted_out" | "opted-out" | "optout" => Some(ExclusionKind::OptedOut),
+            _ => None
         }
     }
 }
 
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DocExclusion {
+    pub kind:ExclusionKind,
+    pub qid:String,
+
+    #[serde(default)]
+    pub other:Option<String>,       // Second person of the pair, none for opted out
+}
+
+impl DocExclusion {
+    /// Whether the exclusion is about the person, opted out persons matter to everyone
+    pub fn involves(&self, qid:&String) -> bool {
+        self.kind == ExclusionKind::OptedOut || &self.qid == qid || self.other.as_ref() == Some(qid)
+    }
+}
+
+impl fmt::Display for DocExclusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<10} {:<10} {:<10}", 
+            self.kind.as_str(), self.qid, self.other.as_ref().map(|other| &other[..]).unwrap_or("-"))
+    }
+}
+
+
+/// All exclusions, to answer who can't be suggested to whom
+pub struct ExclusionList {
+    exclusions:Vec<DocExclusion>,
+}
+
+impl ExclusionList {
+    pub fn new(exclusions:Vec<DocExclusion>) -> Self {
+        Self {
+            exclusions
+        }
+    }
+
+    pub fn is_opted_out(&self, qid:&String) -> bool {
+        self.exclusions.iter().any(|exclusion| exclusion.kind == ExclusionKind::OptedOut && &exclusion.qid == qid)
+    }
+
+    /// Number of persons everyone already met
+    pub fn met_counts(&self) -> HashMap<String, usize> {
+        let mut counts:HashMap<String, usize> = HashMap::new();
+        for exclusion in &self.exclusions {
+            if let (ExclusionKind::Met, Some(other)) = (exclusion.kind, &exclusion.other) {
+                *counts.entry(exclusion.qid.clone()).or_insert(0) += 1;
+                *counts.entry(other.clone()).or_insert(0) += 1;
+            }
+        }
+
+        counts
+    }
+
+    /// Qids never to be suggested to the person, including the person themselves
+    pub fn excluded_for(&self, qid:&String) -> HashSet<String> {
+        let mut excluded:HashSet<String> = HashSet::new();
+        excluded.insert(qid.clone());
+
+        for exclusion in &self.exclusions {
+            match (exclusion.kind, &exclusion.other) {
+                (ExclusionKind::OptedOut, _) => {
+                    excluded.insert(exclusion.qid.clone());
+                }
+
+                //Met and blocked work both ways
+                (_, Some(other)) => {
+                    if &exclusion.qid == qid {
+                        excluded.insert(other.clone());
+                    }else if other == qid {
+                        excluded.insert(exclusion.qid.clone());
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        excluded
+    }
+}
+
+
+
+
+/// What a person did after being matched, feeds the response rating
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseKind {
+    Replied,                        // Answered the match in time
+    NoShow,                         // Didn't turn up for the date
+    LateCancellation,               // Called the date off at the last moment
+}
+
+impl ResponseKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResponseKind::Replied => "replied",
+            ResponseKind::NoShow => "no_show",
+            ResponseKind::LateCancellation => "late_cancellation"
+        }
+    }
+
+    pub fn parse(kind:&str) -> Option<Self> {
+        match &kind.trim().to_lowercase()[..] {
+            "replied" => Some(ResponseKind::Replied),
+            "no_show" | "no-show" | "noshow" => Some(ResponseKind::NoShow),
+            "late_cancellation" | "late-cancellation" | "cancelled" => Some(ResponseKind::LateCancellation),
+            _ => None
+        }
+    }
+
+    /// How much one event of this kind counts against the replies
+    fn weight(&self) -> f32 {
+        match self {
+            ResponseKind::Replied => 1.0,
+            ResponseKind::NoShow => 3.0,
+            ResponseKind::LateCancellation => 2.0
+        }
+    }
+}
+
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DocResponse {
+    pub kind:ResponseKind,
+    pub qid:String,
+}
+
+impl fmt::Display for DocResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<20} {:<10}", self.kind.as_str(), self.qid)
+    }
+}
+
+/// Rating out of 10 from the events of a person, the share of replies with
+/// a no-show counting three times and a late cancellation two times. None without events
+pub fn response_rating(responses:&[DocResponse]) -> Option<f32> {
+    let total:f32 = responses.iter().map(|response| response.kind.weight()).sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let replied:f32 = responses.iter()
+        .filter(|response| response.kind == ResponseKind::Replied)
+        .map(|response| response.kind.weight())
+        .sum();
+    Some(10.0 * replied / total)
+}
+
+/// Rating stored with the person, None if not rated yet
+fn rating_of(rating:&str) -> Option<f32> {
+    rating.trim().parse::<f32>().ok().filter(|rating| *rating >= 0.0 && *rating <= 10.0)
+}
+
+
 
 //Config Files
+
+/// Prefix of the environment variables overriding the config, QDATES_DB_HOST for host and so on
+pub const ENV_PREFIX:&'static str = "QDATES_DB_";
+
+/// Unknown keys are rejected, a misspelled setting would otherwise fall back to its default unnoticed
 #[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct DbConfig {
+    /// Where persons are stored, "mongodb", "sqlite" or "memory"
+    pub backend:String,
+
+    /// Database file for sqlite, optional snapshot file for memory
+    pub path:Option<String>,
+
+    //MongoDB only
+    /// Connection string, mongodb://... or mongodb+srv://..., the fields below are applied over it
+    pub uri:Option<String>,
     pub app:String,
     pub username:String,
     pub password:String,
     pub source:String,
     pub database:String,
     pub host:String,
+    #[serde(deserialize_with = "text")]
     pub port:String,
-}
\ No newline at end of file
+
+    /// Servers as "host" or "host:port", instead of host and port
+    pub hosts:Vec<String>,
+    pub replica_set:Option<String>,
+
+    /// "SCRAM-SHA-256", "SCRAM-SHA-1" or "none", SCRAM-SHA-256 if not given
+    pub auth_mechanism:Option<String>,
+
+    pub tls:bool,
+    pub tls_ca_file:Option<String>,
+    pub tls_cert_key_file:Option<String>,
+    pub tls_allow_invalid_certificates:bool,
+
+    pub connect_timeout_ms:Option<u64>,
+    pub server_selection_timeout_ms:Option<u64>,
+
+    /// "primary", "primaryPreferred", "secondary", "secondaryPreferred" or "nearest"
+    pub read_preference:Option<String>,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        DbConfig {
+            backend:String::from("mongodb"),
+            path:None,
+            uri:None,
+            app:String::new(),
+            username:String::new(),
+            password:String::new(),
+            source:String::new(),
+            database:String::new(),
+            host:String::new(),
+            port:String::new(),
+            hosts:Vec::new(),
+            replica_set:None,
+            auth_mechanism:None,
+            tls:false,
+            tls_ca_file:None,
+            tls_cert_key_file:None,
+            tls_allow_invalid_certificates:false,
+            connect_timeout_ms:None,
+            server_selection_timeout_ms:None,
+            read_preference:None,
+        }
+    }
+}
+
+impl DbConfig {
+    /// Settings of the file with the environment variables applied over it, checked
+    pub fn load(filename:&str) -> Result<Self, DbError> {
+        let mut config:DbConfig = match File::open(filename) {
+            Ok(file) => serde_json::from_reader(file)
+                .map_err(|e| DbError::ConfigError(format!("{} is not valid, {}", filename, e)))?,
+
+            //Everything may be given by the environment instead
+            Err(_) if env::vars().any(|(name, _)| name.starts_with(ENV_PREFIX)) => DbConfig::default(),
+
+            Err(e) => {
+                return Err(DbError::ConfigError(format!("{} can't be read, {}", filename, e)));
+            }
+        };
+
+        config.overrideWith(|field| env::var(format!("{}{}", ENV_PREFIX, field.to_uppercase())).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Replace every field the lookup has a value for, by field name.
+    /// Hosts are separated by commas, an empty value clears an optional field
+    pub fn overrideWith<F>(&mut self, lookup:F) -> Result<(), DbError>
+        where F:Fn(&str) -> Option<String> {
+
+        if let Some(value) = lookup("backend") { self.backend = value; }
+        if let Some(value) = lookup("path") { self.path = optional(value); }
+        if let Some(value) = lookup("uri") { self.uri = optional(value); }
+        if let Some(value) = lookup("app") { self.app = value; }
+        if let Some(value) = lookup("username") { self.username = value; }
+        if let Some(value) = lookup("password") { self.password = value; }
+        if let Some(value) = lookup("source") { self.source = value; }
+        if let Some(value) = lookup("database") { self.database = value; }
+        if let Some(value) = lookup("host") { self.host = value; }
+        if let Some(value) = lookup("port") { self.port = value; }
+        if let Some(value) = lookup("hosts") {
+            self.hosts = value.split(',')
+                .map(|host| host.trim())
+                .filter(|host| !host.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(value) = lookup("replica_set") { self.replica_set = optional(value); }
+        if let Some(value) = lookup("auth_mechanism") { self.auth_mechanism = optional(value); }
+        if let Some(value) = lookup("tls") { self.tls = flag("tls", &value)?; }
+        if let Some(value) = lookup("tls_ca_file") { self.tls_ca_file = optional(value); }
+        if let Some(value) = lookup("tls_cert_key_file") { self.tls_cert_key_file = optional(value); }
+        if let Some(value) = lookup("tls_allow_invalid_certificates") {
+            self.tls_allow_invalid_certificates = flag("tls_allow_invalid_certificates", &value)?;
+        }
+        if let Some(value) = lookup("connect_timeout_ms") {
+            self.connect_timeout_ms = millis("connect_timeout_ms", &value)?;
+        }
+        if let Some(value) = lookup("server_selection_timeout_ms") {
+            self.server_selection_timeout_ms = millis("server_selection_timeout_ms", &value)?;
+        }
+        if let Some(value) = lookup("read_preference") { self.read_preference = optional(value); }
+
+        Ok(())
+    }
+
+    /// Check the settings of the backend, so mistakes show before connecting
+    pub fn validate(&self) -> Result<(), DbError> {
+        match &self.backend.to_lowercase()[..] {
+            "mongodb" | "mongo" => {}
+            "sqlite" | "memory" => return Ok(()),
+            backend => return Err(DbError::UnknownBackend(String::from(backend)))
+        }
+
+        let invalid = |message:&str| Err(DbError::ConfigError(String::from(message)));
+        if self.uri.is_none() && self.hosts.is_empty() && self.host.trim().is_empty() {
+            return invalid("no server given, set uri, hosts or host");
+        }
+        if !self.hosts.is_empty() && !self.host.trim().is_empty() {
+            return invalid("set either hosts or host and port, not both");
+        }
+        if self.database.trim().is_empty() {
+            return invalid("database name is missing");
+        }
+        self.addresses()?;
+
+        for (field, timeout) in &[("connect_timeout_ms", self.connect_timeout_ms), ("server_selection_timeout_ms", self.server_selection_timeout_ms)] {
+            if *timeout == Some(0) {
+                return Err(DbError::ConfigError(format!("{} must be above 0", field)));
+            }
+        }
+
+        for (field, file) in &[("tls_ca_file", &self.tls_ca_file), ("tls_cert_key_file", &self.tls_cert_key_file)] {
+            if let Some(file) = file {
+                if !Path::new(file).exists() {
+                    return Err(DbError::ConfigError(format!("{} {} doesn't exist", field, file)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Servers of hosts, or of host and port, without a port the default one is used.
+    /// IPv6 addresses are written in brackets, like [::1]:27017
+    pub fn addresses(&self) -> Result<Vec<(String, Option<u16>)>, DbError> {
+        if self.hosts.is_empty() {
+            let host = self.host.trim();
+            if host.is_empty() {
+                return Ok(Vec::new());
+            }
+            let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+            return Ok(vec![(String::from(host), port_of(&self.port)?)]);
+        }
+
+        self.hosts.iter().map(|host| address_of(host.trim())).collect()
+    }
+}
+
+/// Host and port of "host", "host:port", "[address]" or "[address]:port".
+/// An IPv6 address without brackets has no port
+fn address_of(host:&str) -> Result<(String, Option<u16>), DbError> {
+    if let Some(bracketed) = host.strip_prefix('[') {
+        let end = bracketed.find(']')
+            .ok_or_else(|| DbError::ConfigError(format!("host {} has no closing bracket", host)))?;
+        let port = match &bracketed[end + 1..] {
+            "" => None,
+            rest if rest.starts_with(':') => port_of(&rest[1..])?,
+            _ => return Err(DbError::ConfigError(format!("host {} has text after the bracket which is not a port", host)))
+        };
+        return Ok((String::from(&bracketed[..end]), port));
+    }
+
+    match host.matches(':').count() {
+        1 => {
+            let index = host.find(':').unwrap_or_default();
+            Ok((String::from(&host[..index]), port_of(&host[index + 1..])?))
+        }
+        _ => Ok((String::from(host), None))
+    }
+}
+
+fn optional(value:String) -> Option<String> {
+    if value.trim().is_empty() { None } else { Some(value) }
+}
+
+/// Port of the config, None for the default one. Anything but 1 to 65535 is refused
+fn port_of(port:&str) -> Result<Option<u16>, DbError> {
+    let port = port.trim();
+    if port.is_empty() {
+        return Ok(None);
+    }
+
+    match port.parse::<u16>() {
+        Ok(number) if number > 0 => Ok(Some(number)),
+        _ => Err(DbError::ConfigError(format!("port {} is not a number from 1 to 65535", port)))
+    }
+}
+
+/// Value of a true/false environment variable
+fn flag(field:&str, value:&str) -> Result<bool, DbError> {
+    match &value.trim().to_lowercase()[..] {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" | "" => Ok(false),
+        _ => Err(DbError::ConfigError(format!("{}{} must be true or false, not {}", ENV_PREFIX, field.to_uppercase(), value)))
+    }
+}
+
+/// Value of a milliseconds environment variable, empty for none
+fn millis(field:&str, value:&str) -> Result<Option<u64>, DbError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    value.parse::<u64>()
+        .map(Some)
+        .map_err(|_| DbError::ConfigError(format!("{}{} must be a number of milliseconds, not {}", ENV_PREFIX, field.to_uppercase(), value)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_round_trip() {
+        for date in &["1970-01-01", "1999-12-31", "2000-02-29", "2024-03-01", "1969-07-20"] {
+            assert_eq!(normalize_date(date).as_deref(), Some(*date));
+        }
+        assert_eq!(normalize_date("1990-5-7").as_deref(), Some("1990-05-07"));
+        assert_eq!(date_of_days(0), "1970-01-01");
+        assert_eq!(normalize_date("1990-13-01"), None);
+    }
+
+    #[test]
+    fn birthdates_cover_the_ages() {
+        let (earliest, latest) = birthdates_between(30.0, 32.0);
+        let ages = (age_from_birthdate(&earliest).unwrap(), age_from_birthdate(&latest).unwrap());
+        assert!(ages.0 > 32.0 && ages.0 < 32.01, "{:?}", ages);
+        assert!(ages.1 < 30.0 && ages.1 > 29.99, "{:?}", ages);
+    }
+
+    #[test]
+    fn migrated_persons_read_blank() {
+        let person:DocPerson = serde_json::from_value(serde_json::json!({
+            "qid":"Q-1", "name":"Asha", "gender":null, "age":30, "email":null, "phone":"+91 98765 43210",
+            "city":"Pune", "languages":["Hindi"], "response_rating":null, "seeking":null
+        })).unwrap();
+        assert_eq!((person.gender.as_str(), person.age.as_str(), person.email.as_str()), ("", "30", ""));
+        assert_eq!((person.seeking.as_str(), person.profession.as_str(), person.rating()), ("", "", None));
+    }
+
+    #[test]
+    fn configs_reject_unknown_keys() {
+        let config:DbConfig = serde_json::from_str(r#"{"backend":"mongodb", "replica_set":"rs0", "port":27017}"#).unwrap();
+        assert_eq!((config.replica_set.as_deref(), config.port.as_str()), (Some("rs0"), "27017"));
+
+        for misspelled in &[r#"{"replicaset":"rs0"}"#, r#"{"tls_ca":"ca.pem"}"#] {
+            let error = serde_json::from_str::<DbConfig>(misspelled).unwrap_err();
+            assert!(error.to_string().starts_with("unknown field"), "{}", error);
+        }
+        assert!(serde_json::from_str::<DbConfig>(include_str!("../../config_sample.json")).is_ok());
+    }
+
+    #[test]
+    fn hosts_take_ipv6_in_brackets() {
+        let mut config = DbConfig::default();
+        config.hosts = vec![String::from("db1:27018"), String::from("[::1]:27019"), String::from("[fe80::1]"),
+                            String::from("fe80::2"), String::from("db2")];
+        assert_eq!(config.addresses().ok().unwrap(), vec![
+            (String::from("db1"), Some(27018)), (String::from("::1"), Some(27019)), (String::from("fe80::1"), None),
+            (String::from("fe80::2"), None), (String::from("db2"), None)
+        ]);
+
+        for broken in &["[::1", "[::1]27019", "[::1]:port", "db1:0"] {
+            config.hosts = vec![String::from(*broken)];
+            assert!(config.addresses().is_err(), "{}", broken);
+        }
+
+        config.hosts = Vec::new();
+        config.host = String::from("[::1]");
+        config.port = String::from("27017");
+        assert_eq!(config.addresses().ok().unwrap(), vec![(String::from("::1"), Some(27017))]);
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let mut config:DbConfig = serde_json::from_str(r#"{
+            "database":"qdates", "hosts":["db1"], "replica_set":"rs0", "tls":false, "connect_timeout_ms":500
+        }"#).unwrap();
+        let environment:HashMap<&str, &str> = [
+            ("hosts", "db2:27018, [::1]:27019,"), ("replica_set", ""), ("tls", "yes"), ("connect_timeout_ms", "2000")
+        ].iter().cloned().collect();
+        config.overrideWith(|field| environment.get(field).map(|value| value.to_string())).ok().unwrap();
+
+        //Fields the environment has no value for keep the one of the file
+        assert_eq!(config.database, "qdates");
+        assert_eq!(config.hosts, vec!["db2:27018", "[::1]:27019"]);
+        assert_eq!((config.replica_set, config.tls, config.connect_timeout_ms), (None, true, Some(2000)));
+
+        let mut config = DbConfig::default();
+        assert!(config.overrideWith(|field| if field == "tls" { Some(String::from("maybe")) } else { None }).is_err());
+        assert!(config.overrideWith(|field| if field == "connect_timeout_ms" { Some(String::from("soon")) } else { None }).is_err());
+    }
+
+    #[test]
+    fn config_files_with_unknown_keys_are_refused() {
+        let path = std::env::temp_dir().join(format!("qdates-config-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"database":"qdates", "host":"db1", "replicaset":"rs0"}"#).unwrap();
+        match DbConfig::load(path.to_str().unwrap()) {
+            Err(DbError::ConfigError(message)) => assert!(message.contains("unknown field `replicaset`"), "{}", message),
+            other => panic!("expected a config error, got {:?}", other)
+        }
+        std::fs::remove_file(path).ok();
+    }
+}
@@ -0,0 +1,942 @@
+//  DbMongo
+//  Storage of the persons in a MongoDB server
+
+use bson::{doc, Bson, Document};
+use std::collections::{HashMap, HashSet};
+use std::iter::{IntoIterator, Iterator};
+use std::time::Duration;
+use mongodb::{
+    Client,
+    Database,
+    options::{
+        auth::{
+            Credential,
+            AuthMechanism
+        },
+    StreamAddress,
+    ClientOptions,
+    ReadPreference,
+    SelectionCriteria,
+    Tls,
+    TlsOptions,
+    FindOneOptions,
+    FindOptions,
+    InsertManyOptions,
+    UpdateOptions
+    }
+};
+use mongodb::error::{Error, ErrorKind, WriteFailure};
+use mongodb::options::IndexModel;
+use super::db_gateway::DbError;
+use super::db_models::{DocPerson, CandidatePersonDb, DbConfig, DocExclusion, DocResponse, birthdates_between};
+use super::db_migrate::{Migration, PLACEHOLDER_FIELDS, latest_version};
+use super::db_store::{PersonStore, Filter, ImportOutcome, IndexSpec, IndexState, IndexReport, PERSON_INDEXES};
+use crate::qdmatch::model::CandidatePerson;
+
+
+
+struct DbIndexModels {
+    iter:Vec<IndexModel>,
+}
+
+struct DbIndexModelIterator {
+    iter:Vec<IndexModel>,
+}
+
+impl Iterator for DbIndexModelIterator {
+
+    type Item = IndexModel;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.pop()
+    }
+}
+
+
+impl IntoIterator for DbIndexModels {
+    type Item = IndexModel;
+    type IntoIter = DbIndexModelIterator;
+
+    fn into_iter(self) -> Self::IntoIter {
+        DbIndexModelIterator {
+            iter:self.iter
+        }
+    }
+}
+
+impl DbIndexModels {
+    fn new() -> Self {
+        Self {
+            iter:Vec::new()
+        }
+    }
+
+    fn add(&mut self, node:IndexModel) {
+        self.iter.push(node);
+    }
+}
+
+
+
+
+const DEFAULT_COLLECTION_PERSON:&'static str = "persons";
+const DEFAULT_COLLECTION_EXCLUSION:&'static str = "exclusions";
+const DEFAULT_COLLECTION_RESPONSE:&'static str = "responses";
+const DEFAULT_COLLECTION_SCHEMA:&'static str = "schema_version";
+
+/// Persons written by one round trip of an import
+const IMPORT_BATCH:usize = 500;
+
+//Server error codes
+const DUPLICATE_KEY:i32 = 11000;
+const NAMESPACE_NOT_FOUND:i32 = 26;
+
+fn is_code(error:&Error, code:i32) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::CommandError(error) => error.code == code,
+        ErrorKind::WriteError(WriteFailure::WriteError(error)) => error.code == code,
+        _ => false
+    }
+}
+
+impl std::convert::From<mongodb::error::Error> for DbError {
+    fn from(m_error:mongodb::error::Error) -> Self {
+        println!("Mongo Error :: {:?}", m_error);
+        DbError::MongoError
+    }
+}
+
+
+/// Query document selecting the same persons as the filter
+fn to_document(filter:&Filter) -> Document {
+    match filter {
+        Filter::All => doc! {},
+
+        Filter::And(conditions) => {
+            if conditions.is_empty() {
+                return doc! {};
+            }
+            let conditions:Vec<Bson> = conditions.iter().map(|condition| Bson::Document(to_document(condition))).collect();
+            doc! {"$and":conditions}
+        }
+
+        Filter::Or(conditions) => {
+            //Mongo refuses an empty $or, nobody matches no condition
+            if conditions.is_empty() {
+                return doc! {"qid":{"$in":[]}};
+            }
+            let conditions:Vec<Bson> = conditions.iter().map(|condition| Bson::Document(to_document(condition))).collect();
+            doc! {"$or":conditions}
+        }
+
+        Filter::Matches(field, patterns) => {
+            let seeks:Vec<Bson> = patterns.iter()
+                .map(|pattern| Bson::RegExp(pattern.regex(), String::from("i")))
+                .collect();
+            doc! { field: {"$in":seeks} }
+        }
+
+        //Age is computed from the birthdate when there is one, like `DocPerson::field_f32` does
+        Filter::Between(field, min, max) if field == "age" => {
+            let (earliest, latest) = birthdates_between(*min, *max);
+            doc! {
+                "$or": [
+                    {"birthdate": {"$gte": earliest, "$lte": latest}},
+                    {"$and": [
+                        {"birthdate": {"$not": Bson::RegExp(String::from(DATE_PATTERN), String::new())}},
+                        number_between(field, *min, *max)
+                    ]}
+                ]
+            }
+        }
+
+        Filter::Between(field, min, max) => number_between(field, *min, *max),
+
+        Filter::NotIn(field, values) => doc! { field: {"$nin":values.clone()} }
+    }
+}
+
+/// Dates as stored, `YYYY-MM-DD`
+const DATE_PATTERN:&'static str = r"^\d{4}-\d{2}-\d{2}$";
+
+/// Numeric field within the bounds
+fn number_between(field:&str, min:f32, max:f32) -> Document {
+    //Numbers may be stored as strings, convert them before comparing
+    let value = doc! {
+        "$convert": {
+            "input": format!("${}", field),
+            "to": "double",
+            "onError": Bson::Null,
+            "onNull": Bson::Null
+        }
+    };
+    doc! {
+        "$expr": {
+            "$and": [
+                {"$gte": [value.clone(), min as f64]},
+                {"$lte": [value, max as f64]}
+            ]
+        }
+    }
+}
+
+/// Blank values are stored as null
+fn text_or_null(value:&str) -> Bson {
+    if value.trim().is_empty() {
+        Bson::Null
+    }else{
+        Bson::String(String::from(value))
+    }
+}
+
+fn number_or_null(value:&str) -> Bson {
+    match value.trim().parse::<f64>() {
+        Ok(number) => Bson::FloatingPoint(number),
+        Err(_) => Bson::Null
+    }
+}
+
+/// Profile fields of a person as stored, without the response rating
+fn profile_document(person:&DocPerson) -> Document {
+    let mut fields = doc! {
+        "qid":&person.qid,
+        "name": &person.name,
+        "gender":text_or_null(&person.gender),
+        "age":number_or_null(&person.age),
+        "email":text_or_null(&person.email),
+        "phone":text_or_null(&person.phone),
+        "city":text_or_null(&person.city),
+        "languages":&person.languages,
+        "profession":text_or_null(&person.profession),
+        "education":text_or_null(&person.education),
+        "verbal_ability":text_or_null(&person.verbal_ability),
+        "seeking":text_or_null(&person.seeking),
+    };
+    if let Some(birthdate) = &person.birthdate {
+        fields.insert("birthdate", birthdate);
+    }
+
+    fields
+}
+
+/// Fields to $set on a person document for the migration, empty if it is migrated already.
+/// `rated` holds the qids with recorded responses
+fn migrated_fields(version:u32, document:&Document, rated:&HashSet<String>) -> Document {
+    let mut fields = doc! {};
+    match version {
+        1 => {
+            if let Ok(age) = document.get_str("age") {
+                fields.insert("age", number_or_null(age));
+            }
+        }
+
+        2 => {
+            if let Ok(rating) = document.get_str("response_rating") {
+                let mut rating = number_or_null(rating);
+
+                //The first importer stored 0 for everyone, without responses it means unrated
+                let qid = document.get_str("qid").unwrap_or_default();
+                if rating == Bson::FloatingPoint(0.0) && !rated.contains(qid) {
+                    rating = Bson::Null;
+                }
+                fields.insert("response_rating", rating);
+            }
+        }
+
+        3 => {
+            for field in PLACEHOLDER_FIELDS {
+                if let Ok(value) = document.get_str(field) {
+                    if value.trim().is_empty() {
+                        fields.insert(*field, Bson::Null);
+                    }
+                }
+            }
+        }
+
+        _ => {}
+    }
+
+    fields
+}
+
+/// Index of the spec, named after it
+fn index_model(spec:&IndexSpec) -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! {spec.field:1})
+        .options(Some(doc! {"name":spec.name, "unique":spec.unique}))
+        .build()
+}
+
+fn insert_document(person:&DocPerson) -> Document {
+    let mut document = profile_document(person);
+    document.insert("response_rating", number_or_null(&person.response_rating));
+    document
+}
+
+
+/// Driver options of the config, the uri first and the other fields over it
+fn client_options(config:&DbConfig) -> Result<ClientOptions, DbError> {
+    let mut options = match &config.uri {
+        Some(uri) => ClientOptions::parse(uri)
+            .map_err(|e| DbError::ConfigError(format!("uri can't be used, {}", e)))?,
+        None => ClientOptions::builder().build()
+    };
+
+    let addresses = config.addresses()?;
+    if !addresses.is_empty() {
+        options.hosts = addresses.into_iter()
+            .map(|(hostname, port)| StreamAddress { hostname, port })
+            .collect();
+    }
+
+    if let Some(replica_set) = &config.replica_set {
+        options.repl_set_name = Some(replica_set.clone());
+    }
+
+    //A single server is talked to directly, unless it is part of a replica set
+    if config.uri.is_none() {
+        options.direct_connection = Some(options.hosts.len() == 1 && options.repl_set_name.is_none());
+    }
+
+    if !config.app.trim().is_empty() {
+        options.app_name = Some(config.app.clone());
+    }
+
+    //Without a username the credential of the uri is kept
+    match auth_mechanism_of(config)? {
+        None => options.credential = None,
+
+        Some(mechanism) if !config.username.trim().is_empty() => {
+            options.credential = Some(Credential::builder()
+                .username(config.username.clone())
+                .password(config.password.clone())
+                .source(Some(config.source.clone()).filter(|source| !source.trim().is_empty()))
+                .mechanism(Some(mechanism))
+                .build());
+        }
+
+        Some(_) => {}
+    }
+
+    if config.tls || config.tls_ca_file.is_some() || config.tls_cert_key_file.is_some() {
+        options.tls = Some(Tls::Enabled(TlsOptions {
+            allow_invalid_certificates:Some(config.tls_allow_invalid_certificates),
+            ca_file_path:config.tls_ca_file.clone(),
+            cert_key_file_path:config.tls_cert_key_file.clone(),
+        }));
+    }
+
+    if let Some(timeout) = config.connect_timeout_ms {
+        options.connect_timeout = Some(Duration::from_millis(timeout));
+    }
+    if let Some(timeout) = config.server_selection_timeout_ms {
+        options.server_selection_timeout = Some(Duration::from_millis(timeout));
+    }
+
+    if let Some(name) = &config.read_preference {
+        options.selection_criteria = Some(SelectionCriteria::ReadPreference(read_preference_of(name)?));
+    }
+
+    Ok(options)
+}
+
+/// Mechanism of the config, None to connect without authentication.
+/// The driver accepts more names but only implements SCRAM
+fn auth_mechanism_of(config:&DbConfig) -> Result<Option<AuthMechanism>, DbError> {
+    let name = match &config.auth_mechanism {
+        Some(name) => name.trim().to_uppercase(),
+        None => return Ok(Some(AuthMechanism::ScramSha256))
+    };
+
+    match &name[..] {
+        "SCRAM-SHA-256" => Ok(Some(AuthMechanism::ScramSha256)),
+        "SCRAM-SHA-1" => Ok(Some(AuthMechanism::ScramSha1)),
+        "NONE" => Ok(None),
+        _ => Err(DbError::ConfigError(format!("auth_mechanism {} is not supported, use SCRAM-SHA-256, SCRAM-SHA-1 or none", name)))
+    }
+}
+
+/// Read preference by its name, as in connection strings
+fn read_preference_of(name:&str) -> Result<ReadPreference, DbError> {
+    match &name.trim().to_lowercase().replace('_', "")[..] {
+        "primary" => Ok(ReadPreference::Primary),
+        "primarypreferred" => Ok(ReadPreference::PrimaryPreferred { tag_sets:None, max_staleness:None }),
+        "secondary" => Ok(ReadPreference::Secondary { tag_sets:None, max_staleness:None }),
+        "secondarypreferred" => Ok(ReadPreference::SecondaryPreferred { tag_sets:None, max_staleness:None }),
+        "nearest" => Ok(ReadPreference::Nearest { tag_sets:None, max_staleness:None }),
+        _ => Err(DbError::ConfigError(format!("read_preference {} is unknown, use primary, primaryPreferred, secondary, secondaryPreferred or nearest", name)))
+    }
+}
+
+/// Single field index found in the database
+struct ListedIndex {
+    name:String,
+    unique:bool,
+}
+
+
+/// Persons, exclusions and responses in a MongoDB database
+pub struct MongoStore {
+    database:Database
+}
+
+impl MongoStore {
+
+    /// Connect to the database of the config, fails if the server can't be reached
+    pub fn connect(config:&DbConfig) -> Result<Self, DbError> {
+
+        let client_options = client_options(config)?;
+        let client = Client::with_options(client_options)?;
+
+        //Select the database
+        let database = client.database(&config.database[..]);
+
+        if let Err(e) = database.list_collections(None, None) {
+            println!("{:?}", e);
+            return Err(DbError::MongoError);
+        }
+
+        Ok(MongoStore {
+            database
+        })
+    }
+
+    /// Single field indexes of the persons by their field
+    fn listIndexes(&self) -> Result<HashMap<String, ListedIndex>, DbError> {
+        let mut listed:HashMap<String, ListedIndex> = HashMap::new();
+        let reply = match self.database.run_command(doc! {"listIndexes":DEFAULT_COLLECTION_PERSON}, None) {
+            Ok(reply) => reply,
+
+            //No persons stored yet, so no indexes either
+            Err(ref e) if is_code(e, NAMESPACE_NOT_FOUND) => return Ok(listed),
+            Err(e) => return Err(e.into())
+        };
+
+        let indexes = reply.get_document("cursor")
+            .and_then(|cursor| cursor.get_array("firstBatch"))
+            .map_err(|_| DbError::DbParsingError)?;
+        for index in indexes {
+            if let Bson::Document(index) = index {
+                let key = match index.get_document("key") {
+                    Ok(key) if key.len() == 1 => key,
+                    _ => continue
+                };
+
+                if let (Some(field), Ok(name)) = (key.keys().next(), index.get_str("name")) {
+                    listed.insert(field.clone(), ListedIndex {
+                        name:String::from(name),
+                        unique:index.get_bool("unique").unwrap_or(false)
+                    });
+                }
+            }
+        }
+
+        Ok(listed)
+    }
+
+    /// Qids of the persons with recorded responses
+    fn ratedQids(&self) -> Result<HashSet<String>, DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_RESPONSE);
+        Ok(collection.distinct("qid", None, None)?.into_iter()
+            .filter_map(|qid| match qid {
+                Bson::String(qid) => Some(qid),
+                _ => None
+            })
+            .collect())
+    }
+
+    /// Qids stored more than once, which keep the unique qid index from being built
+    fn duplicateQids(&self) -> Result<Vec<String>, DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let cursor = collection.aggregate(vec![
+            doc! {"$group":{"_id":"$qid", "count":{"$sum":1}}},
+            doc! {"$match":{"count":{"$gt":1}}}
+        ], None)?;
+
+        let mut qids:Vec<String> = Vec::new();
+        for result in cursor {
+            if let Ok(qid) = result?.get_str("_id") {
+                qids.push(String::from(qid));
+            }
+        }
+
+        qids.sort();
+        Ok(qids)
+    }
+
+    /// Qids out of `qids` which are stored already, in one query
+    fn storedQids(&self, qids:Vec<String>) -> Result<HashSet<String>, DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let options = FindOptions::builder()
+            .projection(doc! {"qid":1})
+            .build();
+
+        let mut stored:HashSet<String> = HashSet::new();
+        for result in collection.find(doc! {"qid":{"$in":qids}}, options)? {
+            if let Ok(qid) = result?.get_str("qid") {
+                stored.insert(String::from(qid));
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Insert the new persons of `batch` with a single unordered insert_many,
+    /// `seen` holds the qids stored or inserted so far
+    fn insertBatch(&self, batch:&[DocPerson], seen:&mut HashSet<String>) -> Vec<ImportOutcome> {
+        let mut outcomes:Vec<ImportOutcome> = Vec::new();
+        let mut rows:Vec<usize> = Vec::new();
+        for (index, person) in batch.iter().enumerate() {
+            if seen.insert(person.qid.clone()) {
+                rows.push(index);
+                outcomes.push(ImportOutcome::Inserted);
+            }else{
+                outcomes.push(ImportOutcome::Duplicate);
+            }
+        }
+
+        if rows.is_empty() {
+            return outcomes;
+        }
+
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let documents:Vec<Document> = rows.iter().map(|index| insert_document(&batch[*index])).collect();
+        let options = InsertManyOptions::builder()
+            .ordered(false)
+            .build();
+
+        if let Err(e) = collection.insert_many(documents, options) {
+            match e.kind.as_ref() {
+                //Only the rows named by the server failed, a qid inserted meanwhile is a duplicate
+                ErrorKind::BulkWriteError(failure) if failure.write_concern_error.is_none() => {
+                    for error in failure.write_errors.iter().flatten() {
+                        if let Some(index) = rows.get(error.index) {
+                            outcomes[*index] = if error.code == DUPLICATE_KEY {
+                                ImportOutcome::Duplicate
+                            }else{
+                                ImportOutcome::Failed(error.message.clone())
+                            };
+                        }
+                    }
+                }
+
+                _ => {
+                    for index in &rows {
+                        outcomes[*index] = ImportOutcome::Failed(e.to_string());
+                    }
+                }
+            }
+        }
+
+        outcomes
+    }
+
+    /// Update the stored persons of `batch` with a single update command
+    fn updateBatch(&self, batch:&[DocPerson], stored:&HashSet<String>) -> Vec<ImportOutcome> {
+        let mut outcomes:Vec<ImportOutcome> = Vec::new();
+        let mut rows:Vec<usize> = Vec::new();
+        let mut updates:Vec<Bson> = Vec::new();
+        for (index, person) in batch.iter().enumerate() {
+            if stored.contains(&person.qid) {
+                rows.push(index);
+                updates.push(Bson::Document(doc! {
+                    "q":{"qid":&person.qid},
+                    "u":{"$set":profile_document(person)},
+                    "upsert":false
+                }));
+                outcomes.push(ImportOutcome::Updated);
+            }else{
+                outcomes.push(ImportOutcome::NotFound);
+            }
+        }
+
+        if rows.is_empty() {
+            return outcomes;
+        }
+
+        let command = doc! {
+            "update":DEFAULT_COLLECTION_PERSON,
+            "updates":updates,
+            "ordered":false
+        };
+        match self.database.run_command(command, None) {
+            Ok(reply) => {
+                if let Ok(errors) = reply.get_array("writeErrors") {
+                    for error in errors {
+                        if let Bson::Document(error) = error {
+                            let index = error.get_i32("index").ok().and_then(|index| rows.get(index as usize));
+                            if let Some(index) = index {
+                                let reason = error.get_str("errmsg").unwrap_or("update refused");
+                                outcomes[*index] = ImportOutcome::Failed(String::from(reason));
+                            }
+                        }
+                    }
+                }
+            }
+
+            Err(e) => {
+                for index in &rows {
+                    outcomes[*index] = ImportOutcome::Failed(e.to_string());
+                }
+            }
+        }
+
+        outcomes
+    }
+}
+
+
+impl PersonStore for MongoStore {
+
+    /// Get a person from the database by searching through its uiq
+    fn getPerson(&mut self, qid:&String) -> Result<DocPerson, DbError> {
+
+        //Get Person
+        let filter = doc! { "qid":qid};
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let find_options = FindOneOptions::builder()
+            .sort(doc!{"name":1})
+            .build();
+
+        match collection.find_one(filter, find_options)? {
+            Some(document) => {
+                match bson::from_bson::<DocPerson>(bson::Bson::Document(document)) {
+                    Ok(person) => Ok(person),
+                    Err(_) => Err(DbError::DbParsingError)
+                }
+            }
+
+            None => Err(DbError::NoPersonFound)
+        }
+    }
+
+    fn search(&mut self, filter:&Filter) -> Result<Vec<DocPerson>, DbError> {
+        let mut persons:Vec<DocPerson> = Vec::new();
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let cursor = collection.find(to_document(filter), None)?;
+        for result in cursor {
+            match result {
+                Ok(document) => {
+                    match bson::from_bson::<DocPerson>(bson::Bson::Document(document)) {
+                        Ok(person) => {
+                            persons.push(person);
+                        }
+                        Err(e) => {
+                            println!("{:?}", e);
+                        }
+                    }
+                }
+
+                Err(_) => {
+                    println!("Error while iterating in db cursor");
+                }
+            }
+        }
+
+        Ok(persons)
+    }
+
+    /// Candidates are read leniently, missing fields get a blank value
+    fn getCandidates(&mut self, filter:&Filter) -> Result<Vec<CandidatePerson>, DbError> {
+        let mut persons:Vec<CandidatePerson> = Vec::new();
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let cursor = collection.find(to_document(filter), None)?;
+        for result in cursor {
+            match result {
+                Ok(document) => {
+                    match bson::from_bson::<CandidatePersonDb>(bson::Bson::Document(document)) {
+                        Ok(personDb) => {
+                            let person:CandidatePerson = personDb.into();
+                            persons.push(person);
+                        }
+                        Err(e) => {
+                            println!("{:?}", e);
+                        }
+                    }
+                }
+
+                Err(_) => {
+                    println!("Error while iterating in db cursor");
+                }
+            }
+        }
+
+        Ok(persons)
+    }
+
+    fn insert(&mut self, person:&DocPerson) -> Result<bool, DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        if collection.find_one(doc! { "qid": &person.qid }, None)?.is_some() {
+            //Entry Already Present
+            return Ok(false);
+        }
+
+        //The unique index still refuses a qid inserted meanwhile
+        match collection.insert_one(insert_document(person), None) {
+            Ok(_) => Ok(true),
+            Err(ref e) if is_code(e, DUPLICATE_KEY) => Ok(false),
+            Err(e) => Err(e.into())
+        }
+    }
+
+    fn update(&mut self, person:&DocPerson) -> Result<bool, DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let result = collection.update_one(doc! { "qid": &person.qid }, doc! { "$set":profile_document(person) }, None)?;
+        Ok(result.matched_count > 0)
+    }
+
+    /// Version recorded in the schema_version collection. A database without
+    /// persons starts at the latest version, one with persons from before migrations at 0
+    fn schemaVersion(&mut self) -> Result<u32, DbError> {
+        let schema = self.database.collection(DEFAULT_COLLECTION_SCHEMA);
+        if let Some(record) = schema.find_one(doc! {"_id":DEFAULT_COLLECTION_PERSON}, None)? {
+            return Ok(record.get_i32("version").unwrap_or(0) as u32);
+        }
+
+        let persons = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        if persons.count_documents(None, None)? > 0 {
+            return Ok(0);
+        }
+
+        self.setSchemaVersion(latest_version())?;
+        Ok(latest_version())
+    }
+
+    fn setSchemaVersion(&mut self, version:u32) -> Result<(), DbError> {
+        let schema = self.database.collection(DEFAULT_COLLECTION_SCHEMA);
+        let options = UpdateOptions::builder()
+            .upsert(true)
+            .build();
+        schema.update_one(doc! {"_id":DEFAULT_COLLECTION_PERSON}, doc! {"$set":{"version":version as i32}}, options)?;
+        Ok(())
+    }
+
+    /// Persons are rewritten one by one, which works on every server version
+    fn applyMigration(&mut self, migration:&Migration, dry_run:bool) -> Result<usize, DbError> {
+        let rated = if migration.version == 2 { self.ratedQids()? } else { HashSet::new() };
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let cursor = collection.find(None, None)?;
+
+        let mut changed = 0;
+        for result in cursor {
+            let document = match result {
+                Ok(document) => document,
+                Err(_) => {
+                    println!("Error while iterating in db cursor");
+                    continue;
+                }
+            };
+
+            let fields = migrated_fields(migration.version, &document, &rated);
+            if fields.is_empty() {
+                continue;
+            }
+
+            changed += 1;
+            if dry_run {
+                continue;
+            }
+
+            if let Ok(id) = document.get_object_id("_id") {
+                collection.update_one(doc! {"_id":id.clone()}, doc! {"$set":fields}, None)?;
+            }
+        }
+
+        Ok(changed)
+    }
+
+    fn indexes(&mut self) -> Result<Vec<IndexReport>, DbError> {
+        let listed = self.listIndexes()?;
+        Ok(PERSON_INDEXES.iter()
+            .map(|spec| {
+                let state = match listed.get(spec.field) {
+                    None => IndexState::Missing,
+                    Some(index) if index.unique == spec.unique => IndexState::Present,
+                    Some(index) => IndexState::Conflicting(format!("{} is {}", index.name, if index.unique { "unique" } else { "not unique" }))
+                };
+                IndexReport {
+                    spec:*spec,
+                    state
+                }
+            })
+            .collect())
+    }
+
+    /// Indexes are created with the createIndexes command, the driver doesn't implement it yet
+    fn ensureIndexes(&mut self, rebuild:bool) -> Result<Vec<IndexReport>, DbError> {
+        let listed = self.listIndexes()?;
+        let mut reports = self.indexes()?;
+        let mut models = DbIndexModels::new();
+
+        for report in reports.iter_mut() {
+            match report.state {
+                IndexState::Missing => models.add(index_model(&report.spec)),
+
+                IndexState::Conflicting(_) if rebuild => {
+                    let name = &listed[report.spec.field].name;
+                    match self.database.run_command(doc! {"dropIndexes":DEFAULT_COLLECTION_PERSON, "index":name}, None) {
+                        Ok(_) => models.add(index_model(&report.spec)),
+                        Err(e) => report.state = IndexState::Failed(e.to_string())
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        for model in models {
+            let IndexModel { keys, options } = model;
+            let name = options.as_ref().and_then(|options| options.get_str("name").ok()).unwrap_or_default().to_string();
+            let mut index = doc! {"key":keys};
+            for (key, value) in options.into_iter().flatten() {
+                index.insert(key, value);
+            }
+
+            let state = match self.database.run_command(doc! {"createIndexes":DEFAULT_COLLECTION_PERSON, "indexes":[index]}, None) {
+                Ok(_) => IndexState::Present,
+                Err(ref e) if is_code(e, DUPLICATE_KEY) => {
+                    let duplicates = self.duplicateQids().unwrap_or_default();
+                    IndexState::Failed(format!("qids stored more than once : {}", duplicates.join(", ")))
+                }
+                Err(e) => IndexState::Failed(e.to_string())
+            };
+
+            if let Some(report) = reports.iter_mut().find(|report| report.spec.name == name) {
+                report.state = state;
+            }
+        }
+
+        Ok(reports)
+    }
+
+    /// Duplicates are found with one query and persons are written with one
+    /// command per `IMPORT_BATCH` persons, instead of round trips for every person
+    fn importPersons(&mut self, persons:&[DocPerson], checkDuplicate:bool) -> Result<Vec<ImportOutcome>, DbError> {
+        let mut outcomes:Vec<ImportOutcome> = Vec::new();
+        let mut seen:HashSet<String> = HashSet::new();
+
+        for batch in persons.chunks(IMPORT_BATCH) {
+            let qids:Vec<String> = batch.iter().map(|person| person.qid.clone()).collect();
+            let stored = self.storedQids(qids)?;
+
+            if checkDuplicate {
+                seen.extend(stored);
+                outcomes.extend(self.insertBatch(batch, &mut seen));
+            }else{
+                outcomes.extend(self.updateBatch(batch, &stored));
+            }
+        }
+
+        Ok(outcomes)
+    }
+
+    fn setResponseRating(&mut self, qid:&String, rating:f32) -> Result<(), DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        collection.update_one(doc! {"qid":qid}, doc! {"$set":{"response_rating":number_or_null(&rating.to_string())}}, None)?;
+        Ok(())
+    }
+
+    fn getExclusions(&mut self, qid:Option<&String>) -> Result<Vec<DocExclusion>, DbError> {
+        let mut exclusions:Vec<DocExclusion> = Vec::new();
+        let collection = self.database.collection(DEFAULT_COLLECTION_EXCLUSION);
+        let filter = match qid {
+            Some(qid) => Some(doc! {
+                "$or": [
+                    {"qid":qid},
+                    {"other":qid},
+                    {"kind":"opted_out"}
+                ]
+            }),
+            None => None
+        };
+
+        let cursor = collection.find(filter, None)?;
+        for result in cursor {
+            match result {
+                Ok(document) => {
+                    match bson::from_bson::<DocExclusion>(bson::Bson::Document(document)) {
+                        Ok(exclusion) => {
+                            exclusions.push(exclusion);
+                        }
+                        Err(e) => {
+                            println!("{:?}", e);
+                        }
+                    }
+                }
+
+                Err(_) => {
+                    println!("Error while iterating in db cursor");
+                }
+            }
+        }
+
+        Ok(exclusions)
+    }
+
+    fn addExclusion(&mut self, exclusion:&DocExclusion) -> Result<(), DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_EXCLUSION);
+        let mut filter = doc! {
+            "kind":exclusion.kind.as_str(),
+            "qid":&exclusion.qid,
+        };
+        if let Some(other) = &exclusion.other {
+            filter.insert("other", other);
+        }
+
+        let options = UpdateOptions::builder()
+            .upsert(true)
+            .build();
+        collection.update_one(filter.clone(), doc! {"$set":filter}, options)?;
+        Ok(())
+    }
+
+    /// Asked from the server, persons which don't parse are counted as well
+    fn distinctValues(&mut self, field:&str) -> Result<Vec<String>, DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_PERSON);
+        let values = collection.distinct(field, None, None)?.into_iter()
+            .filter_map(|value| match value {
+                Bson::String(value) => Some(value),
+                Bson::FloatingPoint(value) => Some(value.to_string()),
+                Bson::I32(value) => Some(value.to_string()),
+                Bson::I64(value) => Some(value.to_string()),
+                _ => None
+            })
+            .collect();
+        Ok(values)
+    }
+
+    fn getResponses(&mut self, qid:&String) -> Result<Vec<DocResponse>, DbError> {
+        let mut responses:Vec<DocResponse> = Vec::new();
+        let collection = self.database.collection(DEFAULT_COLLECTION_RESPONSE);
+        let cursor = collection.find(doc! {"qid":qid}, None)?;
+        for result in cursor {
+            match result {
+                Ok(document) => {
+                    match bson::from_bson::<DocResponse>(bson::Bson::Document(document)) {
+                        Ok(response) => {
+                            responses.push(response);
+                        }
+                        Err(e) => {
+                            println!("{:?}", e);
+                        }
+                    }
+                }
+
+                Err(_) => {
+                    println!("Error while iterating in db cursor");
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+
+    fn insertResponse(&mut self, response:&DocResponse) -> Result<(), DbError> {
+        let collection = self.database.collection(DEFAULT_COLLECTION_RESPONSE);
+        collection.insert_one(doc! {
+            "kind":response.kind.as_str(),
+            "qid":&response.qid,
+        }, None)?;
+        Ok(())
+    }
+}
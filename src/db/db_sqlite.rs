@@ -0,0 +1,502 @@
+//  DbSqlite
+//  Storage of the persons in an embedded SQLite file, for running qdates
+//  on a single machine without a database server.
+
+use std::collections::{HashMap, HashSet};
+use rusqlite::{params, Connection, Row, OptionalExtension, NO_PARAMS};
+use rusqlite::types::Value;
+use super::db_gateway::DbError;
+use super::db_models::{DocPerson, DocExclusion, ExclusionKind, DocResponse, ResponseKind};
+use super::db_migrate::{Migration, PLACEHOLDER_FIELDS, latest_version};
+use super::db_store::{PersonStore, Filter, ImportOutcome};
+
+
+/// Blank values are null, age and response rating are numbers
+const PERSONS_TABLE:&'static str = "
+    CREATE TABLE IF NOT EXISTS persons (
+        qid TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        gender TEXT,
+        age REAL,
+        email TEXT,
+        phone TEXT,
+        city TEXT,
+        languages TEXT NOT NULL,
+        profession TEXT,
+        education TEXT,
+        response_rating REAL,
+        verbal_ability TEXT,
+        seeking TEXT,
+        birthdate TEXT
+    );";
+
+const SCHEMA:&'static str = "
+    CREATE TABLE IF NOT EXISTS exclusions (
+        kind TEXT NOT NULL,
+        qid TEXT NOT NULL,
+        other TEXT NOT NULL DEFAULT '',
+        UNIQUE (kind, qid, other)
+    );
+    CREATE TABLE IF NOT EXISTS responses (
+        kind TEXT NOT NULL,
+        qid TEXT NOT NULL
+    );
+";
+
+const PERSON_COLUMNS:&'static str = "qid, name, gender, age, email, phone, city, languages, profession, education, response_rating, verbal_ability, seeking, birthdate";
+
+impl std::convert::From<rusqlite::Error> for DbError {
+    fn from(s_error:rusqlite::Error) -> Self {
+        println!("SQLite Error :: {:?}", s_error);
+        DbError::SqliteError
+    }
+}
+
+
+/// Text of a column which may hold text, a number or null, like `text` does for documents
+fn text_of(row:&Row, index:usize) -> rusqlite::Result<String> {
+    Ok(match row.get::<_, Value>(index)? {
+        Value::Text(text) => text,
+        Value::Real(number) => number.to_string(),
+        Value::Integer(number) => number.to_string(),
+        Value::Null | Value::Blob(_) => String::new()
+    })
+}
+
+/// Person of a row selected with `PERSON_COLUMNS`, languages are kept as a json list
+fn person_of(row:&Row) -> rusqlite::Result<DocPerson> {
+    Ok(DocPerson {
+        qid:text_of(row, 0)?,
+        name:text_of(row, 1)?,
+        gender:text_of(row, 2)?,
+        age:text_of(row, 3)?,
+        email:text_of(row, 4)?,
+        phone:text_of(row, 5)?,
+        city:text_of(row, 6)?,
+        languages:serde_json::from_str(&text_of(row, 7)?).unwrap_or_default(),
+        profession:text_of(row, 8)?,
+        education:text_of(row, 9)?,
+        response_rating:text_of(row, 10)?,
+        verbal_ability:text_of(row, 11)?,
+        seeking:text_of(row, 12)?,
+        birthdate:row.get(13)?,
+    })
+}
+
+fn languages_of(person:&DocPerson) -> String {
+    serde_json::to_string(&person.languages).unwrap_or(String::from("[]"))
+}
+
+/// Blank values are stored as null
+fn text_or_null(value:&str) -> Value {
+    if value.trim().is_empty() {
+        Value::Null
+    }else{
+        Value::Text(String::from(value))
+    }
+}
+
+fn number_or_null(value:&str) -> Value {
+    match value.trim().parse::<f64>() {
+        Ok(number) => Value::Real(number),
+        Err(_) => Value::Null
+    }
+}
+
+/// Columns to set on a person row for the migration, empty if it is migrated already.
+/// `rated` holds the qids with recorded responses
+fn migrated_columns(version:u32, row:&HashMap<&str, Value>, rated:&HashSet<String>) -> Vec<(&'static str, Value)> {
+    let mut columns:Vec<(&'static str, Value)> = Vec::new();
+    match version {
+        1 => {
+            if let Some(Value::Text(age)) = row.get("age") {
+                columns.push(("age", number_or_null(age)));
+            }
+        }
+
+        2 => {
+            let rating = match row.get("response_rating") {
+                Some(Value::Text(rating)) => rating.trim().parse::<f64>().ok(),
+                Some(Value::Real(rating)) => Some(*rating),
+                Some(Value::Integer(rating)) => Some(*rating as f64),
+                _ => return columns
+            };
+
+            //The first importer stored 0 for everyone, without responses it means unrated
+            let unrated = match row.get("qid") {
+                Some(Value::Text(qid)) => !rated.contains(qid),
+                _ => true
+            };
+            let migrated = match rating {
+                Some(rating) if rating == 0.0 && unrated => Value::Null,
+                Some(rating) => Value::Real(rating),
+                None => Value::Null
+            };
+            columns.push(("response_rating", migrated));
+        }
+
+        3 => {
+            for field in PLACEHOLDER_FIELDS {
+                if let Some(Value::Text(value)) = row.get(field) {
+                    if value.trim().is_empty() {
+                        columns.push((*field, Value::Null));
+                    }
+                }
+            }
+        }
+
+        _ => {}
+    }
+
+    columns.into_iter()
+        .filter(|(column, value)| row.get(column) != Some(value))
+        .collect()
+}
+
+
+/// Persons, exclusions and responses in a SQLite database file
+pub struct SqliteStore {
+    connection:Connection,
+}
+
+impl SqliteStore {
+    /// Open the database file, creating it and its tables if needed
+    pub fn open(path:&str) -> Result<Self, DbError> {
+        Self::prepare(Connection::open(path)?)
+    }
+
+    /// Database which only lives as long as the store
+    pub fn in_memory() -> Result<Self, DbError> {
+        Self::prepare(Connection::open_in_memory()?)
+    }
+
+    /// Create the missing tables, new databases start at the latest schema version
+    fn prepare(connection:Connection) -> Result<Self, DbError> {
+        let existed:bool = connection.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'persons'", NO_PARAMS, |row| row.get(0))?;
+        rebuild_legacy_table(&connection)?;
+        connection.execute_batch(PERSONS_TABLE)?;
+        connection.execute_batch(SCHEMA)?;
+
+        let mut store = SqliteStore {
+            connection
+        };
+        if !existed {
+            store.setSchemaVersion(latest_version())?;
+        }
+
+        Ok(store)
+    }
+}
+
+/// The first release declared every column TEXT NOT NULL, which can't hold the
+/// nulls of the migrations. Its rows are copied into the current table as they are,
+/// numbers stored as text become numbers, `migrate` takes care of the rest
+fn rebuild_legacy_table(connection:&Connection) -> Result<(), DbError> {
+    let age_type:Option<String> = connection.query_row(
+        "SELECT type FROM pragma_table_info('persons') WHERE name = 'age'", NO_PARAMS, |row| row.get(0)).optional()?;
+    if !age_type.map_or(false, |age_type| age_type.eq_ignore_ascii_case("TEXT")) {
+        return Ok(());
+    }
+
+    connection.execute_batch(&format!("
+        BEGIN;
+        ALTER TABLE persons RENAME TO persons_legacy;
+        {}
+        INSERT INTO persons ({}) SELECT {} FROM persons_legacy;
+        DROP TABLE persons_legacy;
+        COMMIT;", PERSONS_TABLE, PERSON_COLUMNS, PERSON_COLUMNS))?;
+    Ok(())
+}
+
+
+impl PersonStore for SqliteStore {
+
+    fn getPerson(&mut self, qid:&String) -> Result<DocPerson, DbError> {
+        let sql = format!("SELECT {} FROM persons WHERE qid = ?1", PERSON_COLUMNS);
+        match self.connection.query_row(&sql, params![qid], person_of).optional()? {
+            Some(person) => Ok(person),
+            None => Err(DbError::NoPersonFound)
+        }
+    }
+
+    /// The filter is checked on every person, events are small enough for it
+    fn search(&mut self, filter:&Filter) -> Result<Vec<DocPerson>, DbError> {
+        let sql = format!("SELECT {} FROM persons ORDER BY qid", PERSON_COLUMNS);
+        let mut statement = self.connection.prepare(&sql)?;
+        let rows = statement.query_map(NO_PARAMS, person_of)?;
+
+        let mut persons:Vec<DocPerson> = Vec::new();
+        for row in rows {
+            let person = row?;
+            if filter.accepts(&person) {
+                persons.push(person);
+            }
+        }
+
+        Ok(persons)
+    }
+
+    fn insert(&mut self, person:&DocPerson) -> Result<bool, DbError> {
+        let sql = format!("INSERT OR IGNORE INTO persons ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)", PERSON_COLUMNS);
+        let inserted = self.connection.execute(&sql, params![
+            person.qid, person.name, text_or_null(&person.gender), number_or_null(&person.age), text_or_null(&person.email),
+            text_or_null(&person.phone), text_or_null(&person.city), languages_of(person), text_or_null(&person.profession),
+            text_or_null(&person.education), number_or_null(&person.response_rating), text_or_null(&person.verbal_ability),
+            text_or_null(&person.seeking), person.birthdate
+        ])?;
+
+        Ok(inserted > 0)
+    }
+
+    fn update(&mut self, person:&DocPerson) -> Result<bool, DbError> {
+        //A birthdate is only ever added, like the $set of the other backends
+        let updated = self.connection.execute("UPDATE persons SET
+                name = ?2, gender = ?3, age = ?4, email = ?5, phone = ?6, city = ?7, languages = ?8,
+                profession = ?9, education = ?10, verbal_ability = ?11, seeking = ?12,
+                birthdate = COALESCE(?13, birthdate)
+            WHERE qid = ?1", params![
+            person.qid, person.name, text_or_null(&person.gender), number_or_null(&person.age), text_or_null(&person.email),
+            text_or_null(&person.phone), text_or_null(&person.city), languages_of(person), text_or_null(&person.profession),
+            text_or_null(&person.education), text_or_null(&person.verbal_ability), text_or_null(&person.seeking),
+            person.birthdate
+        ])?;
+
+        Ok(updated > 0)
+    }
+
+    /// All persons are written in one transaction, instead of syncing the file for every person
+    fn importPersons(&mut self, persons:&[DocPerson], checkDuplicate:bool) -> Result<Vec<ImportOutcome>, DbError> {
+        self.connection.execute_batch("BEGIN")?;
+        let outcomes:Vec<ImportOutcome> = persons.iter()
+            .map(|person| {
+                let stored = if checkDuplicate {
+                    self.insert(person)
+                }else{
+                    self.update(person)
+                };
+                ImportOutcome::of(stored, checkDuplicate)
+            })
+            .collect();
+        self.connection.execute_batch("COMMIT")?;
+
+        Ok(outcomes)
+    }
+
+    fn setResponseRating(&mut self, qid:&String, rating:f32) -> Result<(), DbError> {
+        self.connection.execute("UPDATE persons SET response_rating = ?2 WHERE qid = ?1", params![qid, rating as f64])?;
+        Ok(())
+    }
+
+    fn getExclusions(&mut self, qid:Option<&String>) -> Result<Vec<DocExclusion>, DbError> {
+        let mut statement = self.connection.prepare("SELECT kind, qid, other FROM exclusions")?;
+        let rows = statement.query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+        })?;
+
+        let mut exclusions:Vec<DocExclusion> = Vec::new();
+        for row in rows {
+            let (kind, person, other) = row?;
+            let exclusion = match ExclusionKind::parse(&kind) {
+                Some(kind) => DocExclusion {
+                    kind,
+                    qid:person,
+                    other:if other.is_empty() { None } else { Some(other) }
+                },
+                None => {
+                    println!("Unknown exclusion kind {}", kind);
+                    continue;
+                }
+            };
+
+            if qid.map_or(true, |qid| exclusion.involves(qid)) {
+                exclusions.push(exclusion);
+            }
+        }
+
+        Ok(exclusions)
+    }
+
+    fn addExclusion(&mut self, exclusion:&DocExclusion) -> Result<(), DbError> {
+        self.connection.execute("INSERT OR IGNORE INTO exclusions (kind, qid, other) VALUES (?1, ?2, ?3)", params![
+            exclusion.kind.as_str(), exclusion.qid, exclusion.other.clone().unwrap_or_default()
+        ])?;
+        Ok(())
+    }
+
+    fn getResponses(&mut self, qid:&String) -> Result<Vec<DocResponse>, DbError> {
+        let mut statement = self.connection.prepare("SELECT kind, qid FROM responses WHERE qid = ?1")?;
+        let rows = statement.query_map(params![qid], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut responses:Vec<DocResponse> = Vec::new();
+        for row in rows {
+            let (kind, qid) = row?;
+            match ResponseKind::parse(&kind) {
+                Some(kind) => responses.push(DocResponse { kind, qid }),
+                None => println!("Unknown response kind {}", kind)
+            }
+        }
+
+        Ok(responses)
+    }
+
+    /// Version recorded in the user_version pragma, 0 for databases from before migrations
+    fn schemaVersion(&mut self) -> Result<u32, DbError> {
+        let version:i64 = self.connection.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+        Ok(version as u32)
+    }
+
+    fn setSchemaVersion(&mut self, version:u32) -> Result<(), DbError> {
+        self.connection.execute_batch(&format!("PRAGMA user_version = {}", version))?;
+        Ok(())
+    }
+
+    /// Changed rows are updated in one transaction
+    fn applyMigration(&mut self, migration:&Migration, dry_run:bool) -> Result<usize, DbError> {
+        let rated:HashSet<String> = if migration.version == 2 {
+            let mut statement = self.connection.prepare("SELECT DISTINCT qid FROM responses")?;
+            let qids = statement.query_map(NO_PARAMS, |row| row.get(0))?;
+            qids.collect::<rusqlite::Result<_>>()?
+        }else{
+            HashSet::new()
+        };
+
+        let names:Vec<&str> = PERSON_COLUMNS.split(',').map(str::trim).collect();
+        let rows:Vec<Vec<Value>> = {
+            let mut statement = self.connection.prepare(&format!("SELECT {} FROM persons", PERSON_COLUMNS))?;
+            let rows = statement.query_map(NO_PARAMS, |row| (0..names.len()).map(|index| row.get(index)).collect())?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let transaction = self.connection.transaction()?;
+        let mut changed = 0;
+        for row in rows {
+            let qid = row[0].clone();
+            let row:HashMap<&str, Value> = names.iter().cloned().zip(row).collect();
+            let columns = migrated_columns(migration.version, &row, &rated);
+            if columns.is_empty() {
+                continue;
+            }
+
+            changed += 1;
+            if dry_run {
+                continue;
+            }
+
+            for (column, value) in columns {
+                transaction.execute(&format!("UPDATE persons SET {} = ?2 WHERE qid = ?1", column), params![qid, value])?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(changed)
+    }
+
+    fn insertResponse(&mut self, response:&DocResponse) -> Result<(), DbError> {
+        self.connection.execute("INSERT INTO responses (kind, qid) VALUES (?1, ?2)", params![response.kind.as_str(), response.qid])?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db_migrate::MigrationReport;
+    use crate::qdmatch::testing::person;
+
+    fn stored(qid:&str, name:&str, gender:&str) -> DocPerson {
+        let mut person = person(qid, name, gender).to_person();
+        person.age = String::from("30");
+        person.languages = vec![String::from("English"), String::from("Hindi")];
+        person
+    }
+
+    #[test]
+    fn persons_round_trip() {
+        let mut store = SqliteStore::in_memory().ok().unwrap();
+        let asha = stored("Q-1", "Asha", "Female");
+        let outcomes = store.importPersons(&[asha.clone(), stored("Q-2", "Ravi", "Male"), asha.clone()], true).ok().unwrap();
+        assert_eq!(outcomes, vec![ImportOutcome::Inserted, ImportOutcome::Inserted, ImportOutcome::Duplicate]);
+        assert_eq!(store.getPerson(&String::from("Q-1")).ok(), Some(asha.clone()));
+
+        //Updates keep the rating, which only responses set
+        store.setResponseRating(&String::from("Q-1"), 8.0).ok().unwrap();
+        let mut renamed = asha.clone();
+        renamed.name = String::from("Asha R");
+        assert!(store.update(&renamed).ok().unwrap());
+        let person = store.getPerson(&String::from("Q-1")).ok().unwrap();
+        assert_eq!((person.name.as_str(), person.rating()), ("Asha R", Some(8.0)));
+
+        assert!(!store.update(&stored("Q-9", "Nobody", "Male")).ok().unwrap());
+        match store.getPerson(&String::from("Q-9")) {
+            Err(DbError::NoPersonFound) => {}
+            other => panic!("expected no person, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn candidates_leave_out_exclusions() {
+        let mut store = SqliteStore::in_memory().ok().unwrap();
+        for (qid, name) in &[("Q-2", "Ravi"), ("Q-3", "Arun"), ("Q-4", "Dev")] {
+            store.insert(&stored(qid, name, "Male")).ok().unwrap();
+        }
+        store.addExclusion(&DocExclusion { kind:ExclusionKind::Blocked, qid:String::from("Q-1"), other:Some(String::from("Q-3")) }).ok().unwrap();
+        store.addExclusion(&DocExclusion { kind:ExclusionKind::OptedOut, qid:String::from("Q-4"), other:None }).ok().unwrap();
+
+        let candidates = store.getCandidatesFor(&String::from("Q-1"), Filter::All).ok().unwrap();
+        let qids:Vec<&str> = candidates.iter().map(|candidate| &candidate.qid[..]).collect();
+        assert_eq!(qids, vec!["Q-2"]);
+    }
+
+    #[test]
+    fn responses_need_a_person() {
+        let mut store = SqliteStore::in_memory().ok().unwrap();
+        store.insert(&stored("Q-1", "Asha", "Female")).ok().unwrap();
+        assert!(store.addResponse(&DocResponse { kind:ResponseKind::Replied, qid:String::from("Q-1") }).is_ok());
+        assert!(store.addResponse(&DocResponse { kind:ResponseKind::Replied, qid:String::from("Q-9") }).is_err());
+        assert_eq!(store.getResponses(&String::from("Q-9")).ok().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn new_databases_need_no_migration() {
+        let mut store = SqliteStore::in_memory().ok().unwrap();
+        assert_eq!(store.schemaVersion().ok(), Some(latest_version()));
+        assert!(store.migrate(false).ok().unwrap().is_empty());
+    }
+
+    #[test]
+    fn legacy_databases_migrate() {
+        //Table of the first release, blanks stored as " " and numbers as text
+        let connection = Connection::open_in_memory().ok().unwrap();
+        connection.execute_batch("
+            CREATE TABLE persons (
+                qid TEXT PRIMARY KEY, name TEXT NOT NULL, gender TEXT NOT NULL, age TEXT NOT NULL,
+                email TEXT NOT NULL, phone TEXT NOT NULL, city TEXT NOT NULL, languages TEXT NOT NULL,
+                profession TEXT NOT NULL, education TEXT NOT NULL, response_rating TEXT NOT NULL,
+                verbal_ability TEXT NOT NULL, seeking TEXT NOT NULL, birthdate TEXT
+            );
+            INSERT INTO persons VALUES ('Q-1', 'Asha', 'Female', '30', ' ', ' ', 'Pune', '[]', ' ', ' ', '0', ' ', 'Male', NULL);
+            INSERT INTO persons VALUES ('Q-2', 'Ravi', ' ', ' ', ' ', ' ', ' ', '[]', ' ', ' ', '0', ' ', ' ', NULL);
+            INSERT INTO persons VALUES ('Q-3', 'Arun', 'Male', '32', 'arun@example.com', ' ', 'Pune', '[]', ' ', ' ', '7', ' ', ' ', NULL);").ok().unwrap();
+
+        let mut store = SqliteStore::prepare(connection).ok().unwrap();
+        assert_eq!(store.schemaVersion().ok(), Some(0));
+        store.insertResponse(&DocResponse { kind:ResponseKind::Replied, qid:String::from("Q-1") }).ok().unwrap();
+
+        let counts = |reports:Vec<MigrationReport>| reports.iter().map(|report| report.changed).collect::<Vec<usize>>();
+        assert_eq!(counts(store.migrate(true).ok().unwrap()), vec![1, 1, 3]);
+        assert_eq!(store.schemaVersion().ok(), Some(0));
+        assert_eq!(counts(store.migrate(false).ok().unwrap()), vec![1, 1, 3]);
+        assert_eq!(store.schemaVersion().ok(), Some(latest_version()));
+
+        let asha = store.getPerson(&String::from("Q-1")).ok().unwrap();
+        assert_eq!((asha.age.as_str(), asha.email.as_str(), asha.rating()), ("30", "", Some(0.0)));
+        let ravi = store.getPerson(&String::from("Q-2")).ok().unwrap();
+        assert_eq!((ravi.gender.as_str(), ravi.age.as_str(), ravi.rating()), ("", "", None));
+        let arun = store.getPerson(&String::from("Q-3")).ok().unwrap();
+        assert_eq!((arun.email.as_str(), arun.rating()), ("arun@example.com", Some(7.0)));
+    }
+}
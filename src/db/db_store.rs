@@ -0,0 +1,411 @@
+//  DbStore
+//  Operations every storage backend provides, and the conditions on persons
+//  they are queried with, independent of the database behind them.
+
+use std::fmt;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use super::db_gateway::DbError;
+use super::db_migrate::{Migration, MigrationReport, MIGRATIONS, latest_version};
+use super::db_models::{DocPerson, DocExclusion, ExclusionList, DocResponse, response_rating};
+use crate::qdmatch::model::CandidatePerson;
+use crate::qdmatch::dictionary::Dictionary;
+use crate::qdmatch::geo::{GeoTable, key};
+
+
+/// How a text value is compared, always without case
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Is(String),                     // The whole value
+    Word(String),                   // A whole word of the value
+    Contains(String),               // Any part of the value
+    Key(String),                    // The whole value, ignoring spaces and punctuation as well
+}
+
+impl Pattern {
+    pub fn matches(&self, value:&str) -> bool {
+        let value = value.to_lowercase();
+        match self {
+            Pattern::Is(text) => value.trim() == text.trim().to_lowercase(),
+            Pattern::Word(word) => contains_word(&value, &word.trim().to_lowercase()),
+            Pattern::Contains(text) => value.contains(&text.to_lowercase()),
+            Pattern::Key(text) => {
+                let text = key(text);
+                !text.is_empty() && key(&value) == text
+            }
+        }
+    }
+
+    /// Regular expression matching the same values when used without case
+    pub fn regex(&self) -> String {
+        match self {
+            Pattern::Is(text) => format!("^{}$", escape_regex(text.trim())),
+            Pattern::Word(word) => format!("\\b{}\\b", escape_regex(word.trim())),
+            Pattern::Contains(text) => escape_regex(text),
+
+            //Keys are letters and digits only, anything else may come between them
+            Pattern::Key(text) => {
+                let letters:Vec<String> = key(text).chars().map(|c| c.to_string()).collect();
+                if letters.is_empty() {
+                    return String::from("(?!)");
+                }
+                format!("^[\\W_]*{}[\\W_]*$", letters.join("[\\W_]*"))
+            }
+        }
+    }
+}
+
+/// Whether `word` is in `value` with no letter or digit right before or after it
+fn contains_word(value:&str, word:&str) -> bool {
+    if word.is_empty() {
+        return false;
+    }
+
+    value.match_indices(word).any(|(start, _)| {
+        let before = value[..start].chars().next_back();
+        let after = value[start + word.len()..].chars().next();
+        !before.map_or(false, |c| c.is_alphanumeric()) && !after.map_or(false, |c| c.is_alphanumeric())
+    })
+}
+
+/// Escape a value to be matched literally inside a regular expression
+fn escape_regex(value:&str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+
+/// Condition on persons, every backend turns it into its own query
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    All,
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+
+    /// Text field, or an entry of a list field, matching one of the patterns
+    Matches(String, Vec<Pattern>),
+
+    /// Numeric field within the bounds, numbers stored as text included
+    Between(String, f32, f32),
+
+    /// Field holding none of the values
+    NotIn(String, Vec<String>),
+}
+
+impl Filter {
+    /// Both conditions
+    pub fn and(self, other:Filter) -> Filter {
+        match (self, other) {
+            (Filter::All, other) | (other, Filter::All) => other,
+            (Filter::And(mut conditions), other) => {
+                conditions.push(other);
+                Filter::And(conditions)
+            }
+            (filter, other) => Filter::And(vec![filter, other])
+        }
+    }
+
+    /// Whether the person meets the condition, for backends which can't query it
+    pub fn accepts(&self, person:&DocPerson) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::And(conditions) => conditions.iter().all(|condition| condition.accepts(person)),
+            Filter::Or(conditions) => conditions.iter().any(|condition| condition.accepts(person)),
+
+            Filter::Matches(field, patterns) => {
+                let values:Vec<&String> = match person.field_list(field) {
+                    Some(values) => values.iter().collect(),
+                    None => person.field(field).into_iter().collect()
+                };
+                values.iter().any(|value| patterns.iter().any(|pattern| pattern.matches(value)))
+            }
+
+            Filter::Between(field, min, max) => {
+                match person.field_f32(field) {
+                    Some(value) => value >= *min && value <= *max,
+                    None => false
+                }
+            }
+
+            Filter::NotIn(field, values) => {
+                match person.field(field) {
+                    Some(value) => !values.contains(value),
+                    None => true
+                }
+            }
+        }
+    }
+}
+
+
+/// What became of one person of an import
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportOutcome {
+    Inserted,
+    Updated,
+    Duplicate,                      // Qid stored already, or earlier in the same import
+    NotFound,                       // Nobody stored with the qid to update
+    Failed(String),                 // Database refused the person, with its reason
+}
+
+impl ImportOutcome {
+    pub fn is_stored(&self) -> bool {
+        match self {
+            ImportOutcome::Inserted | ImportOutcome::Updated => true,
+            _ => false
+        }
+    }
+
+    /// Outcome of storing one person with `insert` or `update`
+    pub(crate) fn of(stored:Result<bool, DbError>, checkDuplicate:bool) -> Self {
+        match (stored, checkDuplicate) {
+            (Ok(true), true) => ImportOutcome::Inserted,
+            (Ok(true), false) => ImportOutcome::Updated,
+            (Ok(false), true) => ImportOutcome::Duplicate,
+            (Ok(false), false) => ImportOutcome::NotFound,
+            (Err(e), _) => ImportOutcome::Failed(e.to_string())
+        }
+    }
+}
+
+impl fmt::Display for ImportOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportOutcome::Inserted => write!(f, "inserted"),
+            ImportOutcome::Updated => write!(f, "updated"),
+            ImportOutcome::Duplicate => write!(f, "duplicate"),
+            ImportOutcome::NotFound => write!(f, "not found"),
+            ImportOutcome::Failed(reason) => write!(f, "failed : {}", reason)
+        }
+    }
+}
+
+
+/// Index the persons should have
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexSpec {
+    pub name:&'static str,
+    pub field:&'static str,
+    pub unique:bool,
+}
+
+/// Indexes ensured on connect. The unique qid keeps concurrent imports from storing
+/// a person twice, the others serve the candidate queries and the search by name
+pub const PERSON_INDEXES:&'static [IndexSpec] = &[
+    IndexSpec { name:"qid_unique", field:"qid", unique:true },
+    IndexSpec { name:"gender", field:"gender", unique:false },
+    IndexSpec { name:"age", field:"age", unique:false },
+    IndexSpec { name:"city", field:"city", unique:false },
+    IndexSpec { name:"name", field:"name", unique:false },
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexState {
+    Present,
+    Missing,
+    Conflicting(String),            // An index on the field differs, rebuilt on repair
+    Failed(String),                 // Database refused to create it, with its reason
+    NotUsed,                        // Backend doesn't query by the field
+}
+
+impl IndexState {
+    pub fn is_ok(&self) -> bool {
+        match self {
+            IndexState::Present | IndexState::NotUsed => true,
+            _ => false
+        }
+    }
+}
+
+pub struct IndexReport {
+    pub spec:IndexSpec,
+    pub state:IndexState,
+}
+
+impl fmt::Display for IndexReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match &self.state {
+            IndexState::Present => String::from("present"),
+            IndexState::Missing => String::from("missing"),
+            IndexState::Conflicting(reason) => format!("conflicting : {}", reason),
+            IndexState::Failed(reason) => format!("failed : {}", reason),
+            IndexState::NotUsed => String::from("not used")
+        };
+        write!(f, "{:<12} {:<10} {:<8} {}", self.spec.name, self.spec.field, if self.spec.unique { "unique" } else { "" }, state)
+    }
+}
+
+
+/// Storage of persons, their exclusions and their responses
+pub trait PersonStore {
+
+    /// Person with the qid, `DbError::NoPersonFound` if there is none
+    fn getPerson(&mut self, qid:&String) -> Result<DocPerson, DbError>;
+
+    fn search(&mut self, filter:&Filter) -> Result<Vec<DocPerson>, DbError>;
+
+    /// Store a new person, false if the qid is taken already
+    fn insert(&mut self, person:&DocPerson) -> Result<bool, DbError>;
+
+    /// Replace the profile of a stored person, false if the qid is unknown.
+    /// The response rating is kept, it is only set by the responses
+    fn update(&mut self, person:&DocPerson) -> Result<bool, DbError>;
+
+    fn setResponseRating(&mut self, qid:&String, rating:f32) -> Result<(), DbError>;
+
+    /// Exclusions involving the person, all of them without a qid.
+    /// Opted out persons are always included, they are excluded for everyone
+    fn getExclusions(&mut self, qid:Option<&String>) -> Result<Vec<DocExclusion>, DbError>;
+
+    /// Store an exclusion, storing the same one again changes nothing
+    fn addExclusion(&mut self, exclusion:&DocExclusion) -> Result<(), DbError>;
+
+    fn getResponses(&mut self, qid:&String) -> Result<Vec<DocResponse>, DbError>;
+
+    fn insertResponse(&mut self, response:&DocResponse) -> Result<(), DbError>;
+
+
+    /// State of the indexes of `PERSON_INDEXES`. Backends without indexes keep
+    /// qids unique by themselves and scan for everything else
+    fn indexes(&mut self) -> Result<Vec<IndexReport>, DbError> {
+        Ok(PERSON_INDEXES.iter()
+            .map(|spec| IndexReport {
+                spec:*spec,
+                state:if spec.unique { IndexState::Present } else { IndexState::NotUsed }
+            })
+            .collect())
+    }
+
+    /// Create the missing indexes, and rebuild the conflicting ones when `rebuild`
+    fn ensureIndexes(&mut self, _rebuild:bool) -> Result<Vec<IndexReport>, DbError> {
+        self.indexes()
+    }
+
+    /// Migration version the stored persons are at. Backends without
+    /// migrations always store persons the latest way
+    fn schemaVersion(&mut self) -> Result<u32, DbError> {
+        Ok(latest_version())
+    }
+
+    fn setSchemaVersion(&mut self, _version:u32) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    /// Apply one migration to every stored person, or only count them on a dry run.
+    /// Returns the number of persons it changes
+    fn applyMigration(&mut self, _migration:&Migration, _dry_run:bool) -> Result<usize, DbError> {
+        Ok(0)
+    }
+
+    /// Apply every migration above the schema version in order, recording the version after each
+    fn migrate(&mut self, dry_run:bool) -> Result<Vec<MigrationReport>, DbError> {
+        let version = self.schemaVersion()?;
+        let mut reports:Vec<MigrationReport> = Vec::new();
+        for migration in MIGRATIONS.iter().filter(|migration| migration.version > version) {
+            let changed = self.applyMigration(migration, dry_run)?;
+            if !dry_run {
+                self.setSchemaVersion(migration.version)?;
+            }
+
+            reports.push(MigrationReport {
+                version:migration.version,
+                description:migration.description,
+                changed,
+                dry_run
+            });
+        }
+
+        Ok(reports)
+    }
+
+    fn getCandidates(&mut self, filter:&Filter) -> Result<Vec<CandidatePerson>, DbError> {
+        Ok(self.search(filter)?.iter().map(CandidatePerson::from).collect())
+    }
+
+    /// Candidates for a person, leaving out everyone excluded for them
+    fn getCandidatesFor(&mut self, qid:&String, filter:Filter) -> Result<Vec<CandidatePerson>, DbError> {
+        let excluded:Vec<String> = self.excludedFor(qid)?.into_iter().collect();
+        self.getCandidates(&filter.and(Filter::NotIn(String::from("qid"), excluded)))
+    }
+
+    /// Qids never to be suggested to the person
+    fn excludedFor(&mut self, qid:&String) -> Result<HashSet<String>, DbError> {
+        let exclusions = self.getExclusions(Some(qid))?;
+        Ok(ExclusionList::new(exclusions).excluded_for(qid))
+    }
+
+    /// Number of persons each person already met, for breaking ties
+    fn metCounts(&mut self) -> Result<HashMap<String, usize>, DbError> {
+        Ok(ExclusionList::new(self.getExclusions(None)?).met_counts())
+    }
+
+    /// Every person with every exclusion, for matching everyone at once
+    fn everyone(&mut self) -> Result<(Vec<CandidatePerson>, ExclusionList), DbError> {
+        let persons = self.getCandidates(&Filter::All)?;
+        Ok((persons, ExclusionList::new(self.getExclusions(None)?)))
+    }
+
+    /// Insert new persons, or update the stored ones when `checkDuplicate` is false.
+    /// One outcome per person in the same order, backends override it to write in batches
+    fn importPersons(&mut self, persons:&[DocPerson], checkDuplicate:bool) -> Result<Vec<ImportOutcome>, DbError> {
+        Ok(persons.iter()
+            .map(|person| {
+                let stored = if checkDuplicate {
+                    self.insert(person)
+                }else{
+                    self.update(person)
+                };
+                ImportOutcome::of(stored, checkDuplicate)
+            })
+            .collect())
+    }
+
+    /// Rewrite the stored persons with canonical values and city names,
+    /// the way `excel::read` stores new ones. Returns the number of persons changed
+    fn normalizePersons(&mut self, dictionary:&Dictionary, cities:&GeoTable) -> Result<usize, DbError> {
+        let mut changed = 0;
+        for person in self.search(&Filter::All)? {
+            let mut normalized = person.clone();
+            dictionary.normalize_person(&mut normalized);
+            normalized.city = cities.normalize(&normalized.city);
+
+            if normalized != person && self.update(&normalized)? {
+                changed += 1;
+            }
+        }
+
+        Ok(changed)
+    }
+
+    /// Every value of the field over all persons, entries of lists one by one
+    fn distinctValues(&mut self, field:&str) -> Result<Vec<String>, DbError> {
+        let mut values:BTreeSet<String> = BTreeSet::new();
+        for person in self.search(&Filter::All)? {
+            match person.field_list(field) {
+                Some(list) => values.extend(list.iter().cloned()),
+                None => values.extend(person.field(field).cloned())
+            }
+        }
+
+        Ok(values.into_iter().collect())
+    }
+
+    /// Record what a person did and store their new response rating with them,
+    /// `DbError::NoPersonFound` if nobody has the qid
+    fn addResponse(&mut self, response:&DocResponse) -> Result<Option<f32>, DbError> {
+        self.getPerson(&response.qid)?;
+        self.insertResponse(response)?;
+
+        let rating = response_rating(&self.getResponses(&response.qid)?);
+        if let Some(rating) = rating {
+            self.setResponseRating(&response.qid, rating)?;
+        }
+
+        Ok(rating)
+    }
+}
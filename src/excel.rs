@@ -1,5 +1,7 @@
-use crate::db::db_models::DocPerson;
-use calamine::{Reader, open_workbook, Xlsx, Error, RangeDeserializerBuilder, RangeDeserializer};
+use crate::db::db_models::{DocPerson, date_of_days, normalize_date};
+use crate::qdmatch::geo::GeoTable;
+use crate::qdmatch::dictionary::Dictionary;
+use calamine::{Reader, open_workbook, Xlsx, Error, DataType, RangeDeserializerBuilder, RangeDeserializer};
 
 
 #[derive(Debug)]
@@ -29,6 +31,29 @@ impl<'a> std::convert::From<calamine::DeError> for ExcelError {
 }
 
 
+/// Languages of a cell, separated by comma, semicolon or slash, in the order given
+fn parse_languages(cell:&str) -> Vec<String> {
+    cell.split(|c| c == ',' || c == ';' || c == '/')
+        .map(|language| language.trim())
+        .filter(|language| !language.is_empty())
+        .map(|language| language.to_string())
+        .collect()
+}
+
+/// Headers of the birthdate column
+const BIRTHDATE_HEADERS:[&'static str; 4] = ["birthdate", "birth date", "date of birth", "dob"];
+
+/// Birthdate `YYYY-MM-DD` of a date cell or of text like 1990-05-17, None if the cell has none
+fn parse_birthdate(cell:&DataType) -> Option<String> {
+    match cell {
+        //Date cells hold the days since 1899-12-30
+        DataType::Float(serial) => Some(date_of_days(serial.floor() as i64 - 25569)),
+        DataType::Int(serial) => Some(date_of_days(serial - 25569)),
+        DataType::String(text) => normalize_date(text),
+        _ => None
+    }
+}
+
 //Read the data from file
 pub fn read<'a>(path:String) -> Result<(Vec<DocPerson>, Vec<String>),ExcelError> {
     let mut workbook:Xlsx<_> = open_workbook(path)?;
@@ -39,6 +64,10 @@ pub fn read<'a>(path:String) -> Result<(Vec<DocPerson>, Vec<String>),ExcelError>
     //Collection to store error/waring about rows while reading
     let mut warnings:Vec<String> = Vec::new();
 
+    //Cities are stored by the name in the geo table, other values by their canonical one
+    let cities = GeoTable::bundled();
+    let dictionary = Dictionary::bundled();
+
     //Extract out the sheets
     let sheet_count = workbook.sheet_names().len();
 
@@ -59,6 +88,14 @@ pub fn read<'a>(path:String) -> Result<(Vec<DocPerson>, Vec<String>),ExcelError>
             String, // Verbal Ability
             String, // Seeking
         )> = RangeDeserializerBuilder::new().from_range(&range)?;
+
+        //Languages and birthdate columns are optional and found by their header
+        let rows:Vec<&[calamine::DataType]> = range.rows().collect();
+        let column = |names:&[&str]| rows.first().and_then(|header| {
+            header.iter().position(|cell| names.contains(&&cell.to_string().trim().to_lowercase()[..]))
+        });
+        let languages_column = column(&["languages"]);
+        let birthdate_column = column(&BIRTHDATE_HEADERS);
         
         //We Got some data  
         for (index, row) in row_iter.enumerate() {
@@ -80,7 +117,21 @@ pub fn read<'a>(path:String) -> Result<(Vec<DocPerson>, Vec<String>),ExcelError>
                 
                 // Check for valid qid. TODO : Pattern matching 'Q-{1..}'
                 if qid.contains("Q-") {
-                    persons.push(DocPerson {
+                    //Sheets without the column keep the old default
+                    let languages = match languages_column {
+                        Some(column) => rows.get(index + 1)
+                            .and_then(|cells| cells.get(column))
+                            .map(|cell| parse_languages(&cell.to_string()))
+                            .unwrap_or_default(),
+                        None => vec![String::from("English")]
+                    };
+
+                    //Age is computed from the birthdate when the sheet has one
+                    let birthdate = birthdate_column
+                        .and_then(|column| rows.get(index + 1).and_then(|cells| cells.get(column)))
+                        .and_then(parse_birthdate);
+
+                    let mut person = DocPerson {
                         qid:qid,
                         name:name,
                         email:email,
@@ -88,13 +139,16 @@ pub fn read<'a>(path:String) -> Result<(Vec<DocPerson>, Vec<String>),ExcelError>
                         profession:profession,
                         age:(age as u64).to_string(),
                         gender:gender,
-                        response_rating:String::from("0"),
-                        city:city,
+                        response_rating:String::new(),
+                        city:cities.normalize(&city),
                         seeking:seeking,
                         verbal_ability:verbal_ability,
                         education:education,
-                        languages:vec![String::from("English")],
-                    });
+                        languages:languages,
+                        birthdate:birthdate,
+                    };
+                    dictionary.normalize_person(&mut person);
+                    persons.push(person);
                 }else{
                     warnings.push(format!("Invalid qid in sheet {} row {}", sheet_index + 1, index));
                 }
@@ -3,10 +3,130 @@ pub mod db;
 pub mod qdmatch;
 
 
-use clap::{Arg, App, SubCommand};
+use clap::{Arg, App, SubCommand, value_t};
 use db::{db_gateway};
-use qdmatch::matcher::Match;
-use bson::{doc};
+use db::db_store::{PersonStore, Filter, Pattern, ImportOutcome, IndexState};
+use db::db_migrate::latest_version;
+use db::db_models::{DocPerson, DocExclusion, ExclusionKind, DocResponse, ResponseKind, response_rating};
+use qdmatch::matcher::{Match, MatchError, Lookups};
+use qdmatch::{pairing, schedule, batch, lint};
+use qdmatch::lint::KnownValues;
+use std::collections::HashMap;
+use qdmatch::dictionary::Dictionary;
+use qdmatch::geo::GeoTable;
+
+fn is_seed(value:String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("seed must be a positive number"))
+    }
+}
+
+/// Value of the argument, None if it isn't given. Exits with the usage error
+/// if it isn't a `T`, for arguments which have no validator
+fn value_of<T:std::str::FromStr>(args:&clap::ArgMatches, name:&str) -> Option<T> {
+    args.value_of(name)?;
+    Some(value_t!(args, name, T).unwrap_or_else(|e| e.exit()))
+}
+
+fn seed_of(args:&clap::ArgMatches) -> Option<u64> {
+    value_of(args, "seed")
+}
+
+fn is_count(value:String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(()),
+        _ => Err(String::from("must be a number above zero"))
+    }
+}
+
+fn is_score(value:String) -> Result<(), String> {
+    match value.parse::<f32>() {
+        Ok(score) if score >= 0.0 && score <= 100.0 => Ok(()),
+        _ => Err(String::from("score must be a number from 0 to 100"))
+    }
+}
+
+fn is_offset(value:String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("offset must be a positive number"))
+    }
+}
+
+fn is_export(value:String) -> Result<(), String> {
+    let value = value.to_lowercase();
+    if value.ends_with(".csv") || value.ends_with(".json") {
+        return Ok(());
+    }
+    Err(String::from("matches are exported to a .csv or .json file, open the .csv in Excel for a spreadsheet"))
+}
+
+fn min_score_of(args:&clap::ArgMatches) -> f32 {
+    value_of(args, "min-score").unwrap_or_default()
+}
+
+/// Persons which an import couldn't store, each with its outcome and the reason when the database gave one
+fn print_not_stored(persons:&[DocPerson], outcomes:&[ImportOutcome]) {
+    let count = outcomes.iter().filter(|outcome| !outcome.is_stored()).count();
+    if count == 0 {
+        return;
+    }
+
+    println!("- Following entries were not stored [{}]", count);
+    for (person, outcome) in persons.iter().zip(outcomes).filter(|(_, outcome)| !outcome.is_stored()) {
+        println!("{}  {}", person, outcome);
+    }
+}
+
+/// Print the findings of the rules file, exit with an error code if it has errors
+fn check_rules(filename:&str, with_db:bool) {
+    let text = match std::fs::read_to_string(filename) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("{} : {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut values:KnownValues = HashMap::new();
+    if with_db {
+        let mut db = match db_gateway::DbGateway::new("config.json") {
+            Ok(db) => db,
+            Err(e) => {
+                println!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = db.connect() {
+            println!("{:?}", e);
+            std::process::exit(1);
+        }
+
+        for field in &["gender", "education", "verbal_ability", "city", "profession", "seeking", "languages"] {
+            match db.distinctValues(field) {
+                Ok(found) => {
+                    values.insert(field.to_string(), found.into_iter().collect());
+                }
+                Err(e) => println!("{}", e)
+            }
+        }
+        if let Some(verbal) = values.get("verbal_ability").cloned() {
+            values.insert(String::from("verbal"), verbal);
+        }
+    }
+
+    let diagnostics = lint::check(&text, if with_db { Some(&values) } else { None });
+    for diagnostic in &diagnostics {
+        println!("{}: {}", filename, diagnostic);
+    }
+
+    let errors = diagnostics.iter().filter(|diagnostic| diagnostic.is_error()).count();
+    println!("{} errors, {} warnings", errors, diagnostics.len() - errors);
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
 
 fn main(){
 
@@ -42,20 +162,209 @@ fn main(){
             .arg(Arg::with_name("QID")
                 .help("QID of the person for whom the date is to be qurated.")
                 .required(true)
+                .index(1))
+            .arg(Arg::with_name("rules")
+                .long("rules")
+                .takes_value(true)
+                .default_value("rules.json")
+                .help("Rules file, either rules.json or qd_rules.json format"))
+            .arg(Arg::with_name("mutual")
+                .long("mutual")
+                .help("Score both ways, candidates must also be interested in the person"))
+            .arg(Arg::with_name("explain")
+                .long("explain")
+                .help("Show how every rule scored each candidate"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("Print the candidates as json"))
+            .arg(Arg::with_name("min-score")
+                .long("min-score")
+                .takes_value(true)
+                .validator(is_score)
+                .help("Leave out candidates scoring less, 0 to 100"))
+            .arg(Arg::with_name("limit")
+                .long("limit")
+                .takes_value(true)
+                .validator(is_count)
+                .help("Most candidates to show"))
+            .arg(Arg::with_name("offset")
+                .long("offset")
+                .takes_value(true)
+                .default_value("0")
+                .validator(is_offset)
+                .help("Ranked candidates to skip, for the next page"))
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .validator(is_seed)
+                .help("Break ties of equal score randomly with this seed, by fewest prior matches then qid otherwise")))
+        .subcommand(SubCommand::with_name("pair-event")
+            .about("Pair every person one to one, stable for both sides")
+            .version("0.0")
+            .arg(Arg::with_name("rules")
+                .long("rules")
+                .takes_value(true)
+                .default_value("rules.json")
+                .help("Rules file, either rules.json or qd_rules.json format"))
+            .arg(Arg::with_name("proposers")
+                .long("proposers")
+                .takes_value(true)
+                .default_value("Female")
+                .help("Gender of the side which proposes, everyone else receives"))
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .validator(is_seed)
+                .help("Break ties of equal score randomly with this seed, by fewest prior matches then qid otherwise")))
+        .subcommand(SubCommand::with_name("match-all")
+            .about("Top matches of every person, the candidates are loaded once")
+            .version("0.0")
+            .arg(Arg::with_name("rules")
+                .long("rules")
+                .takes_value(true)
+                .default_value("rules.json")
+                .help("Rules file, either rules.json or qd_rules.json format"))
+            .arg(Arg::with_name("top")
+                .long("top")
+                .takes_value(true)
+                .default_value("10")
+                .validator(is_count)
+                .help("Number of matches kept for every person"))
+            .arg(Arg::with_name("min-score")
+                .long("min-score")
+                .takes_value(true)
+                .validator(is_score)
+                .help("Leave out candidates scoring less, 0 to 100"))
+            .arg(Arg::with_name("mutual")
+                .long("mutual")
+                .help("Only keep candidates whose own policy accepts the person, score both directions"))
+            .arg(Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .validator(is_export)
+                .help("Export the matches to a .csv or .json file, Excel files are not written but Excel opens the .csv"))
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .validator(is_seed)
+                .help("Break ties of equal score randomly with this seed, by fewest prior matches then qid otherwise")))
+        .subcommand(SubCommand::with_name("schedule")
+            .about("Speed dating rounds, no pair meets twice")
+            .version("0.0")
+            .arg(Arg::with_name("ROUNDS")
+                .help("Number of rounds")
+                .required(true)
+                .validator(is_count)
+                .index(1))
+            .arg(Arg::with_name("TABLES")
+                .help("Number of tables")
+                .required(true)
+                .validator(is_count)
+                .index(2))
+            .arg(Arg::with_name("rules")
+                .long("rules")
+                .takes_value(true)
+                .default_value("rules.json")
+                .help("Rules file, either rules.json or qd_rules.json format"))
+            .arg(Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .help("Export the schedule to a csv file"))
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .validator(is_seed)
+                .help("Break ties of equal score randomly with this seed, by fewest prior matches then qid otherwise")))
+        .subcommand(SubCommand::with_name("exclude")
+            .about("Keep persons out of each other's matches")
+            .version("0.0")
+            .arg(Arg::with_name("KIND")
+                .help("met, blocked or opted_out")
+                .required(true)
+                .possible_values(&["met", "blocked", "opted_out"])
+                .index(1))
+            .arg(Arg::with_name("QID")
+                .help("QID of the person")
+                .required(true)
+                .index(2))
+            .arg(Arg::with_name("OTHER")
+                .help("QID of the other person, for met and blocked")
+                .required_ifs(&[("KIND", "met"), ("KIND", "blocked")])
+                .index(3)))
+        .subcommand(SubCommand::with_name("exclusions")
+            .about("List exclusions")
+            .version("0.0")
+            .arg(Arg::with_name("QID")
+                .help("Only exclusions involving this person")
+                .index(1)))
+        .subcommand(SubCommand::with_name("rules")
+            .about("Work with rules files")
+            .version("0.0")
+            .subcommand(SubCommand::with_name("check")
+                .about("Check a rules file for mistakes, with the line of each finding")
+                .version("0.0")
+                .arg(Arg::with_name("FILE")
+                    .help("Rules file, either rules.json or qd_rules.json format")
+                    .default_value("rules.json")
+                    .index(1))
+                .arg(Arg::with_name("db")
+                    .long("db")
+                    .help("Also report values which no person in the database has"))))
+        .subcommand(SubCommand::with_name("indexes")
+            .about("Show the indexes of the persons, missing ones are created on every connect")
+            .version("0.0")
+            .arg(Arg::with_name("repair")
+                .long("repair")
+                .help("Rebuild indexes which differ from the expected ones")))
+        .subcommand(SubCommand::with_name("migrate")
+            .about("Update how persons are stored to the latest schema version")
+            .version("0.0")
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Only count the persons each migration would change")))
+        .subcommand(SubCommand::with_name("normalize")
+            .about("Rewrite stored persons with the canonical values of the dictionary and the city table")
+            .version("0.0"))
+        .subcommand(SubCommand::with_name("respond")
+            .about("Record how a person responded to a match and update their response rating")
+            .version("0.0")
+            .arg(Arg::with_name("KIND")
+                .help("replied, no_show or late_cancellation")
+                .required(true)
+                .possible_values(&["replied", "no_show", "late_cancellation"])
+                .index(1))
+            .arg(Arg::with_name("QID")
+                .help("QID of the person")
+                .required(true)
+                .index(2)))
+        .subcommand(SubCommand::with_name("responses")
+            .about("List the responses of a person and their response rating")
+            .version("0.0")
+            .arg(Arg::with_name("QID")
+                .help("QID of the person")
+                .required(true)
                 .index(1)))
         .get_matches();
 
 
-        
-    //Create Database
-    let mut db;
-    if let Some(_db) = db_gateway::DbGateway::new("config.json"){
-        db = _db;
-    }else{
-        println!("Bad config file !");
+
+    //Rules are checked without a database unless asked for
+    if let Some(args) = matches.subcommand_matches("rules") {
+        if let Some(args) = args.subcommand_matches("check") {
+            check_rules(args.value_of("FILE").unwrap(), args.is_present("db"));
+        }
         return;
     }
 
+    //Create Database
+    let mut db = match db_gateway::DbGateway::new("config.json") {
+        Ok(db) => db,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
     //Connect Database
     if let Err(e) = db.connect() {
         println!("{:?}", e);
@@ -83,16 +392,10 @@ fn main(){
                     }
 
                     
-                    match db.insertAndCheckDuplicate(person_collection, false) {
-                        Ok(db_result) =>  {
+                    match db.importPersons(&person_collection, false) {
+                        Ok(outcomes) =>  {
                             //Unsucessfull entries
-                            if db_result.len() > 0 {
-                                println!("- Following entires failed - [Duplicate Entries]");
-                                for person in db_result {
-                                    println!("{}", person);
-                                }
-                            }
-                            
+                            print_not_stored(&person_collection, &outcomes);
                         }
 
                         Err(e) => {
@@ -112,49 +415,116 @@ fn main(){
             let nameToSearch = matches.subcommand_matches("search").unwrap().value_of("NAME").unwrap();
 
             //Prepare the filter to match
-            let filter =    doc! {
-                                    "$or": [
-                                                {"name": { "$regex": &nameToSearch, "$options": "i" }},
-                                                {"qid": { "$regex": &nameToSearch, "$options": "i" }}
-                                            ]
-                                };
+            let filter = Filter::Or(vec![
+                                    Filter::Matches(String::from("name"), vec![Pattern::Contains(String::from(nameToSearch))]),
+                                    Filter::Matches(String::from("qid"), vec![Pattern::Contains(String::from(nameToSearch))])
+                                ]);
             //Search all the candidates
             
-            let candidates = db.getCandidates(filter).unwrap();
-            if candidates.len() > 0 {
-                for candidate in candidates {
-                    candidate.print_detail();
+            match db.getCandidates(&filter) {
+                Ok(candidates) if candidates.len() > 0 => {
+                    for candidate in candidates {
+                        candidate.print_detail();
+                    }
                 }
-            }else{
-                println!("No match found :(");
+                Ok(_) => println!("No match found :("),
+                Err(e) => println!("{}", e)
             }
         }
 
         Some("match") => {
             let qid = matches.subcommand_matches("match").unwrap().value_of("QID").unwrap();
+            let rules = matches.subcommand_matches("match").unwrap().value_of("rules").unwrap();
+            let mutual = matches.subcommand_matches("match").unwrap().is_present("mutual");
+            let explain = matches.subcommand_matches("match").unwrap().is_present("explain");
+            let json = matches.subcommand_matches("match").unwrap().is_present("json");
+            let seed = seed_of(matches.subcommand_matches("match").unwrap());
+            let args = matches.subcommand_matches("match").unwrap();
             match db.getPerson(&String::from(qid)) {
-                Ok(_personLookingForDate) => {
-                    let personLookingForDate = _personLookingForDate.unwrap();
-                    println!("Matching for ...");
-                    println!("{}", personLookingForDate);
-                    println!("************************************************************");
-
-                    if let Ok(_matcher) = Match::new(personLookingForDate, String::from("rules.json")) {
-                        let mut matcher = _matcher.unwrap();
-                        let filter = matcher.getFilter();
-                        let candidates = db.getCandidates(filter).unwrap();
-
-                        let candidatesSorted = matcher.qurate(candidates);
-                        if candidatesSorted.len() > 0 {
-                            for candidate in candidatesSorted {
-                                println!("{}", candidate);
+                Ok(personLookingForDate) => {
+                    if !json {
+                        println!("Matching for ...");
+                        println!("{}", personLookingForDate);
+                        if let Some(seed) = seed {
+                            println!("Seed : {}", seed);
+                        }
+                        println!("************************************************************");
+                    }
+
+                    let rules = match Match::loadRules(&String::from(rules)) {
+                        Ok(rules) => rules,
+                        Err(e) => {
+                            println!("Policy sheet is not correct : {}", e);
+                            return;
+                        }
+                    };
+
+                    let lookups = Lookups::bundled();
+                    match Match::withRules(personLookingForDate, &rules, &lookups) {
+                        Ok(_matcher) => {
+                            let mut matcher = _matcher.unwrap();
+                            let filter = matcher.getFilter();
+                            //Candidates come without anyone excluded for the person
+                            let (candidates, met) = match (db.getCandidatesFor(&String::from(qid), filter), db.metCounts()) {
+                                (Ok(candidates), Ok(met)) => (candidates, met),
+                                (Err(e), _) | (_, Err(e)) => {
+                                    println!("{}", e);
+                                    return;
+                                }
+                            };
+                            matcher.priorMatches(met);
+                            matcher.seed(seed);
+                            matcher.minScore(min_score_of(args));
+                            matcher.page(value_of(args, "offset").unwrap_or_default(), value_of(args, "limit"));
+
+                            let mut candidatesSorted = if mutual {
+                                matcher.qurateMutual(candidates, &rules, &lookups)
+                            }else{
+                                matcher.qurate(candidates)
+                            };
+
+                            if explain && mutual {
+                                matcher.explainMutual(&mut candidatesSorted, &rules, &lookups);
+                            }else if explain {
+                                matcher.explain(&mut candidatesSorted);
+                            }
+
+                            if json {
+                                //Seed is recorded to run the same match again
+                                let output = serde_json::json!({"qid":qid, "seed":seed, "candidates":candidatesSorted});
+                                println!("{}", serde_json::to_string_pretty(&output).unwrap());
+                            }else if candidatesSorted.len() > 0 {
+                                for candidate in candidatesSorted {
+                                    println!("{}", candidate);
+                                    if mutual && explain {
+                                        println!("    {} seeks {}", qid, candidate.qid);
+                                    }
+                                    for detail in &candidate.explanation {
+                                        println!("{}", detail);
+                                    }
+                                    if !candidate.their_explanation.is_empty() {
+                                        println!("    {} seeks {}", candidate.qid, qid);
+                                    }
+                                    for detail in &candidate.their_explanation {
+                                        println!("{}", detail);
+                                    }
+                                }
+                            }else{
+                                println!("No match found :(");
                             }
-                        }else{
-                            println!("No match found :(");
                         }
-                        
-                    }else{
-                        println!("Policy sheet is not correct for this candidate");
+
+                        Err(MatchError::InvalidRule(reason)) => {
+                            println!("Policy sheet is not correct : {}", reason);
+                        }
+
+                        Err(e @ MatchError::NoPolicy(..)) => {
+                            println!("Policy sheet has {}, add one for them", e);
+                        }
+
+                        Err(_) => {
+                            println!("Policy sheet is not correct for this candidate");
+                        }
                     }
                 }
                 
@@ -165,6 +535,250 @@ fn main(){
             }
         }
         
+        Some("pair-event") => {
+            let args = matches.subcommand_matches("pair-event").unwrap();
+            let rules = match Match::loadRules(&String::from(args.value_of("rules").unwrap())) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    println!("Policy sheet is not correct : {}", e);
+                    return;
+                }
+            };
+
+            let (persons, exclusions) = match db.everyone() {
+                Ok(everyone) => everyone,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            let pairing = pairing::pair_event(persons, &rules, &Lookups::bundled(), &exclusions, args.value_of("proposers").unwrap(), seed_of(args));
+
+            if let Some(seed) = seed_of(args) {
+                println!("Seed : {}", seed);
+            }
+
+            println!("- Pairs [{}]", pairing.pairs.len());
+            for pair in &pairing.pairs {
+                println!("{:<10} {:<40} {:<10} {:<40} {:>4}% {:>4}%", 
+                    pair.first.qid, pair.first.name, pair.second.qid, pair.second.name, 
+                    (pair.first_score as u8), (pair.second_score as u8));
+            }
+
+            if pairing.unpaired.len() > 0 {
+                println!("- Unpaired [{}]", pairing.unpaired.len());
+                for person in &pairing.unpaired {
+                    person.print_detail();
+                }
+            }
+
+            if pairing.excluded.len() > 0 {
+                println!("- Left out [{}]", pairing.excluded.len());
+                for excluded in &pairing.excluded {
+                    println!("{:<10} {:<40} {}", excluded.person.qid, excluded.person.name, excluded.reason);
+                }
+            }
+        }
+
+        Some("match-all") => {
+            let args = matches.subcommand_matches("match-all").unwrap();
+            let output = args.value_of("output");
+
+            let rules = match Match::loadRules(&String::from(args.value_of("rules").unwrap())) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    println!("Policy sheet is not correct : {}", e);
+                    return;
+                }
+            };
+
+            let (persons, exclusions) = match db.everyone() {
+                Ok(everyone) => everyone,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            let options = batch::MatchAllOptions {
+                top:value_of(args, "top").unwrap_or(batch::MatchAllOptions::default().top),
+                min_score:min_score_of(args),
+                mutual:args.is_present("mutual"),
+                seed:seed_of(args)
+            };
+            let all = batch::match_all(persons, &rules, &Lookups::bundled(), &exclusions, options, |done, total| {
+                eprint!("\rMatching {} of {}", done, total);
+                if done == total {
+                    eprintln!();
+                }
+            });
+            print!("{}", all);
+
+            if let Some(filename) = output {
+                if let Err(e) = all.export(filename) {
+                    println!("{:?}", e);
+                }
+            }
+        }
+
+        Some("schedule") => {
+            let args = matches.subcommand_matches("schedule").unwrap();
+            let rounds:usize = value_of(args, "ROUNDS").unwrap();
+            let tables:usize = value_of(args, "TABLES").unwrap();
+
+            let rules = match Match::loadRules(&String::from(args.value_of("rules").unwrap())) {
+                Ok(rules) => rules,
+                Err(e) => {
+                    println!("Policy sheet is not correct : {}", e);
+                    return;
+                }
+            };
+
+            let (persons, exclusions) = match db.everyone() {
+                Ok(everyone) => everyone,
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            };
+            let schedule = schedule::schedule(persons, &rules, &Lookups::bundled(), &exclusions, rounds, tables, seed_of(args));
+            print!("{}", schedule);
+
+            if let Some(filename) = args.value_of("output") {
+                if let Err(e) = schedule.export(filename) {
+                    println!("{:?}", e);
+                }
+            }
+        }
+
+        Some("exclude") => {
+            let args = matches.subcommand_matches("exclude").unwrap();
+            let kind = ExclusionKind::parse(args.value_of("KIND").unwrap()).unwrap();
+            let exclusion = DocExclusion {
+                kind,
+                qid:String::from(args.value_of("QID").unwrap()),
+                other:match kind {
+                    ExclusionKind::OptedOut => None,
+                    _ => args.value_of("OTHER").map(String::from)
+                }
+            };
+
+            match db.addExclusion(&exclusion) {
+                Ok(()) => println!("{}", exclusion),
+                Err(e) => println!("{}", e)
+            }
+        }
+
+        Some("exclusions") => {
+            let qid = matches.subcommand_matches("exclusions").unwrap().value_of("QID").map(String::from);
+            match db.getExclusions(qid.as_ref()) {
+                Ok(exclusions) => {
+                    if exclusions.len() > 0 {
+                        for exclusion in exclusions {
+                            println!("{}", exclusion);
+                        }
+                    }else{
+                        println!("No exclusions found");
+                    }
+                }
+
+                Err(e) => {
+                    println!("{}", e);
+                }
+            }
+        }
+
+        Some("indexes") => {
+            let repair = matches.subcommand_matches("indexes").unwrap().is_present("repair");
+            let reports = if repair {
+                db.ensureIndexes(true)
+            }else{
+                db.indexes()
+            };
+
+            match reports {
+                Ok(reports) => {
+                    for report in &reports {
+                        println!("{}", report);
+                    }
+
+                    if !repair && reports.iter().any(|report| if let IndexState::Conflicting(_) = report.state { true } else { false }) {
+                        println!("Run indexes --repair to rebuild the conflicting indexes");
+                    }
+                }
+                Err(e) => println!("{}", e)
+            }
+        }
+
+        Some("migrate") => {
+            let dry_run = matches.subcommand_matches("migrate").unwrap().is_present("dry-run");
+            match db.schemaVersion() {
+                Ok(version) => println!("Schema version : {} of {}", version, latest_version()),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+
+            match db.migrate(dry_run) {
+                Ok(reports) => {
+                    if reports.is_empty() {
+                        println!("Nothing to migrate");
+                    }
+                    for report in reports {
+                        println!("{}", report);
+                    }
+                }
+                Err(e) => println!("{}", e)
+            }
+        }
+
+        Some("normalize") => {
+            match db.normalizePersons(&Dictionary::bundled(), &GeoTable::bundled()) {
+                Ok(changed) => println!("Normalized {} persons", changed),
+                Err(e) => println!("{}", e)
+            }
+        }
+
+        Some("respond") => {
+            let args = matches.subcommand_matches("respond").unwrap();
+            let response = DocResponse {
+                kind:ResponseKind::parse(args.value_of("KIND").unwrap()).unwrap(),
+                qid:String::from(args.value_of("QID").unwrap())
+            };
+
+            match db.addResponse(&response) {
+                Ok(rating) => {
+                    println!("{}", response);
+                    if let Some(rating) = rating {
+                        println!("Response rating : {:.1}", rating);
+                    }
+                }
+                Err(e) => println!("{}", e)
+            }
+        }
+
+        Some("responses") => {
+            let qid = String::from(matches.subcommand_matches("responses").unwrap().value_of("QID").unwrap());
+            match db.getResponses(&qid) {
+                Ok(responses) => {
+                    if responses.len() > 0 {
+                        for response in &responses {
+                            println!("{}", response);
+                        }
+                        if let Some(rating) = response_rating(&responses) {
+                            println!("Response rating : {:.1}", rating);
+                        }
+                    }else{
+                        println!("No responses found");
+                    }
+                }
+
+                Err(e) => {
+                    println!("{}", e);
+                }
+            }
+        }
+
         Some("insert") =>  {
             let filename = matches.subcommand_matches("insert").unwrap().value_of("INPUT").unwrap();
 
@@ -179,16 +793,10 @@ fn main(){
                     }
 
                     
-                    match db.insertAndCheckDuplicate(person_collection, true) {
-                        Ok(db_result) =>  {
+                    match db.importPersons(&person_collection, true) {
+                        Ok(outcomes) =>  {
                             //Unsucessfull entries
-                            if db_result.len() > 0 {
-                                println!("- Following entires failed - [Duplicate Entries]");
-                                for person in db_result {
-                                    println!("{}", person);
-                                }
-                            }
-                            
+                            print_not_stored(&person_collection, &outcomes);
                         }
 
                         Err(e) => {
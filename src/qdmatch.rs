@@ -1,3 +1,12 @@
 pub mod matcher;
 pub mod model;
-pub mod rules;
\ No newline at end of file
+pub mod rules;
+pub mod pairing;
+pub mod schedule;
+pub mod geo;
+pub mod dictionary;
+pub mod batch;
+pub mod lint;
+
+#[cfg(test)]
+pub(crate) mod testing;
@@ -0,0 +1,264 @@
+//  Batch
+//  Matches of every person against one candidate pool, loaded once,
+//  for preparing a whole event in a single run.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use serde::Serialize;
+use super::matcher::{Match, Lookups};
+use super::model::CandidatePerson;
+use super::rules::RuleBook;
+use crate::db::db_models::ExclusionList;
+
+
+/// Best matches of one person
+#[derive(Serialize)]
+pub struct PersonMatches {
+    pub qid:String,
+    pub name:String,
+
+    /// False if no policy of the rules applies to the person
+    pub has_policy:bool,
+    pub matches:Vec<CandidatePerson>,
+}
+
+#[derive(Serialize)]
+pub struct MatchAll {
+    pub top:usize,
+    pub min_score:f32,
+    pub seed:Option<u64>,
+    pub results:Vec<PersonMatches>,
+}
+
+
+/// How the matches of every person are ranked and trimmed
+#[derive(Debug, Clone, Copy)]
+pub struct MatchAllOptions {
+    /// Number of matches kept for every person
+    pub top:usize,
+
+    /// Candidates scoring less are left out
+    pub min_score:f32,
+
+    /// Only keep candidates whose own policy accepts the person, score both directions
+    pub mutual:bool,
+
+    /// Break ties randomly with this seed
+    pub seed:Option<u64>,
+}
+
+impl Default for MatchAllOptions {
+    fn default() -> Self {
+        MatchAllOptions {
+            top:10,
+            min_score:0.0,
+            mutual:false,
+            seed:None
+        }
+    }
+}
+
+
+/// Top matches by `options` for every person out of `persons`, in qid order.
+/// Opted out persons are left out as seekers and as candidates.
+/// `progress` is called with the number of persons done and the total after every person.
+pub fn match_all<F>(persons:Vec<CandidatePerson>, rules:&RuleBook, lookups:&Lookups, exclusions:&ExclusionList, options:MatchAllOptions, mut progress:F) -> MatchAll
+    where F:FnMut(usize, usize) {
+    let mut persons:Vec<CandidatePerson> = persons.into_iter()
+        .filter(|person| !exclusions.is_opted_out(&person.qid))
+        .collect();
+    persons.sort_by(|a, b| a.qid.cmp(&b.qid));
+    let prior_matches = exclusions.met_counts();
+
+    let mut results:Vec<PersonMatches> = Vec::new();
+    for (index, person) in persons.iter().enumerate() {
+        let mut result = PersonMatches {
+            qid:person.qid.clone(),
+            name:person.name.clone(),
+            has_policy:false,
+            matches:Vec::new()
+        };
+
+        if let Ok(Some(mut matcher)) = Match::withRules(person.to_person(), rules, lookups) {
+            matcher.exclude(exclusions.excluded_for(&person.qid));
+            matcher.priorMatches(prior_matches.clone());
+            matcher.seed(options.seed);
+            matcher.minScore(options.min_score);
+            matcher.page(0, Some(options.top));
+
+            let candidates = persons.clone();
+            let matches = if options.mutual {
+                matcher.qurateMutual(candidates, rules, lookups)
+            }else{
+                matcher.qurate(candidates)
+            };
+
+            result.has_policy = true;
+            result.matches = matches;
+        }
+
+        results.push(result);
+        progress(index + 1, persons.len());
+    }
+
+    MatchAll {
+        top:options.top,
+        min_score:options.min_score,
+        seed:options.seed,
+        results
+    }
+}
+
+
+impl MatchAll {
+
+    /// Persons with a policy but nobody to match
+    pub fn unmatched(&self) -> Vec<&PersonMatches> {
+        self.results.iter().filter(|result| result.has_policy && result.matches.is_empty()).collect()
+    }
+
+    pub fn without_policy(&self) -> Vec<&PersonMatches> {
+        self.results.iter().filter(|result| !result.has_policy).collect()
+    }
+
+    /// Write the matches as json when the file name ends with `.json`, as csv otherwise.
+    /// Excel files are not written, Excel opens the csv
+    pub fn export(&self, filename:&str) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        if filename.to_lowercase().ends_with(".json") {
+            return serde_json::to_writer_pretty(file, self).map_err(|e| e.into());
+        }
+
+        writeln!(file, "qid,name,rank,match_qid,match_name,score,seed")?;
+        let seed = self.seed.map(|seed| seed.to_string()).unwrap_or_default();
+
+        for result in &self.results {
+            for (rank, candidate) in result.matches.iter().enumerate() {
+                writeln!(file, "{},\"{}\",{},{},\"{}\",{},{}",
+                    result.qid, result.name.replace("\"", "\"\""),
+                    rank + 1,
+                    candidate.qid, candidate.name.replace("\"", "\"\""),
+                    (candidate.match_score as u8), seed)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for MatchAll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(seed) = self.seed {
+            writeln!(f, "Seed : {}", seed)?;
+        }
+
+        for result in &self.results {
+            writeln!(f, "- {} {} [{} matches]", result.qid, result.name, result.matches.len())?;
+            for candidate in &result.matches {
+                writeln!(f, "{}", candidate)?;
+            }
+        }
+
+        let matched = self.results.iter().filter(|result| result.matches.len() > 0).count();
+        writeln!(f, "************************************************************")?;
+        writeln!(f, "Matched : {} of {}", matched, self.results.len())?;
+
+        let unmatched:Vec<&str> = self.unmatched().iter().map(|result| &result.qid[..]).collect();
+        if unmatched.len() > 0 {
+            writeln!(f, "No matches : {}", unmatched.join(", "))?;
+        }
+
+        let without_policy:Vec<&str> = self.without_policy().iter().map(|result| &result.qid[..]).collect();
+        if without_policy.len() > 0 {
+            writeln!(f, "No policy : {}", without_policy.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db_memory::MemoryStore;
+    use crate::db::db_models::{DocExclusion, ExclusionKind};
+    use crate::db::db_store::PersonStore;
+    use crate::qdmatch::testing::{person, ranking};
+
+    /// Event in a store: a prefers d over e, b seeks e, e accepts nobody, c has no policy and f opted out
+    fn event() -> (Vec<CandidatePerson>, ExclusionList) {
+        let mut store = MemoryStore::new();
+        for (qid, name, gender) in &[("Q-1", "a", "Female"), ("Q-2", "b \"B\"", "Female"), ("Q-3", "c", "Nonbinary"),
+                                     ("Q-4", "d", "Male"), ("Q-5", "e", "Male"), ("Q-6", "f", "Male")] {
+            store.insert(&person(qid, name, gender).to_person()).ok().unwrap();
+        }
+        store.addExclusion(&DocExclusion { kind:ExclusionKind::OptedOut, qid:String::from("Q-6"), other:None }).ok().unwrap();
+        store.everyone().ok().unwrap()
+    }
+
+    fn rules() -> RuleBook {
+        ranking(&[("Q-1", &["d", "e", "f"]), ("Q-2", &["e", "f"]), ("Q-4", &["a"]), ("Q-5", &[]), ("Q-6", &["a", "b \"B\""])])
+    }
+
+    fn matched(result:&MatchAll) -> Vec<(&str, Vec<&str>)> {
+        result.results.iter()
+            .map(|result| (&result.qid[..], result.matches.iter().map(|candidate| &candidate.qid[..]).collect()))
+            .collect()
+    }
+
+    fn qids(results:Vec<&PersonMatches>) -> Vec<&str> {
+        results.into_iter().map(|result| &result.qid[..]).collect()
+    }
+
+    #[test]
+    fn options_trim_the_matches() {
+        let (persons, exclusions) = event();
+        let rules = rules();
+        let run = |options:MatchAllOptions| match_all(persons.clone(), &rules, &Lookups::bundled(), &exclusions, options, |_, _| {});
+
+        let all = run(MatchAllOptions::default());
+        assert_eq!(matched(&all), vec![("Q-1", vec!["Q-4", "Q-5"]), ("Q-2", vec!["Q-5"]), ("Q-3", vec![]), ("Q-4", vec!["Q-1"]), ("Q-5", vec![])]);
+        assert_eq!(qids(all.unmatched()), vec!["Q-5"]);
+        assert_eq!(qids(all.without_policy()), vec!["Q-3"]);
+
+        let top = run(MatchAllOptions { top:1, ..MatchAllOptions::default() });
+        assert_eq!(matched(&top)[0], ("Q-1", vec!["Q-4"]));
+
+        //e scores 66 for a, listed second of three
+        let best = run(MatchAllOptions { min_score:70.0, ..MatchAllOptions::default() });
+        assert_eq!(matched(&best)[0], ("Q-1", vec!["Q-4"]));
+
+        let mutual = run(MatchAllOptions { mutual:true, ..MatchAllOptions::default() });
+        assert_eq!(matched(&mutual), vec![("Q-1", vec!["Q-4"]), ("Q-2", vec![]), ("Q-3", vec![]), ("Q-4", vec!["Q-1"]), ("Q-5", vec![])]);
+        assert_eq!(qids(mutual.unmatched()), vec!["Q-2", "Q-5"]);
+    }
+
+    #[test]
+    fn exports_record_the_seed() {
+        let (persons, exclusions) = event();
+        let options = MatchAllOptions { top:1, seed:Some(7), ..MatchAllOptions::default() };
+        let mut calls:Vec<(usize, usize)> = Vec::new();
+        let result = match_all(persons, &rules(), &Lookups::bundled(), &exclusions, options, |done, total| calls.push((done, total)));
+        assert_eq!(calls.last(), Some(&(5, 5)));
+
+        let path = std::env::temp_dir().join(format!("qdates-match-all-{}", std::process::id()));
+        let csv = path.with_extension("csv");
+        result.export(csv.to_str().unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(&csv).unwrap(), "qid,name,rank,match_qid,match_name,score,seed\n\
+            Q-1,\"a\",1,Q-4,\"d\",100,7\n\
+            Q-2,\"b \"\"B\"\"\",1,Q-5,\"e\",100,7\n\
+            Q-4,\"d\",1,Q-1,\"a\",100,7\n");
+
+        let json = path.with_extension("json");
+        result.export(json.to_str().unwrap()).unwrap();
+        let exported:serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&json).unwrap()).unwrap();
+        assert_eq!((exported["seed"].as_u64(), exported["top"].as_u64()), (Some(7), Some(1)));
+        assert_eq!(exported["results"][2]["has_policy"], serde_json::json!(false));
+        assert_eq!(exported["results"][3]["matches"][0]["qid"], serde_json::json!("Q-1"));
+
+        std::fs::remove_file(csv).ok();
+        std::fs::remove_file(json).ok();
+    }
+}
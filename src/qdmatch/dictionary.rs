@@ -0,0 +1,82 @@
+//  Dictionary
+//  Canonical values of categorical fields and their other spellings, bundled
+//  from `dictionary.json` at build time.
+
+use std::collections::BTreeMap;
+use serde::Deserialize;
+use super::geo::key;
+use crate::db::db_models::DocPerson;
+
+
+const DICTIONARY:&'static str = include_str!("../../dictionary.json");
+
+
+/// A canonical value and the spellings which stand for it
+#[derive(Debug, Deserialize, Clone)]
+pub struct Synonyms {
+    pub canonical:String,
+
+    #[serde(default)]
+    pub synonyms:Vec<String>,
+}
+
+impl Synonyms {
+    /// Whether the value is the canonical one or a synonym, ignoring case, spaces and dots
+    pub fn contains(&self, value:&str) -> bool {
+        let value = key(value);
+        !value.is_empty() && (key(&self.canonical) == value || self.synonyms.iter().any(|synonym| key(synonym) == value))
+    }
+}
+
+/// Canonical value of the first entry holding the value, the trimmed value if there is none
+pub fn normalize(synonyms:&[Synonyms], value:&str) -> String {
+    match synonyms.iter().find(|entry| entry.contains(value)) {
+        Some(entry) => entry.canonical.clone(),
+        None => value.trim().to_string()
+    }
+}
+
+
+/// Synonyms by field name
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Dictionary {
+    #[serde(flatten)]
+    fields:BTreeMap<String, Vec<Synonyms>>,
+}
+
+impl Dictionary {
+    /// Dictionary shipped with the program
+    pub fn bundled() -> Self {
+        serde_json::from_str(DICTIONARY).expect("dictionary.json is not a valid dictionary")
+    }
+
+    pub fn fields(&self) -> Vec<&String> {
+        self.fields.keys().collect()
+    }
+
+    pub fn synonyms_for(&self, field:&str) -> &[Synonyms] {
+        match self.fields.get(field) {
+            Some(synonyms) => synonyms,
+            None => &[]
+        }
+    }
+
+    pub fn normalize(&self, field:&str, value:&str) -> String {
+        normalize(self.synonyms_for(field), value)
+    }
+
+    /// Replace every value of the person which has a canonical one
+    pub fn normalize_person(&self, person:&mut DocPerson) {
+        for (field, synonyms) in &self.fields {
+            if let Some(value) = person.field_mut(field) {
+                *value = normalize(synonyms, value);
+            }
+
+            if let Some(values) = person.field_list_mut(field) {
+                for value in values.iter_mut() {
+                    *value = normalize(synonyms, value);
+                }
+            }
+        }
+    }
+}
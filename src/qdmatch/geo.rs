@@ -0,0 +1,114 @@
+//  Geo
+//  Offline table of city coordinates and their other spellings, bundled
+//  from `cities.json` at build time.
+
+use std::rc::Rc;
+use serde::Deserialize;
+
+
+const CITIES:&'static str = include_str!("../../cities.json");
+
+/// Mean radius of the earth in km
+const EARTH_RADIUS:f64 = 6371.0;
+
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct City {
+    pub name:String,
+
+    /// Other names and spellings of the city
+    #[serde(default)]
+    pub aliases:Vec<String>,
+
+    pub lat:f64,
+    pub lon:f64,
+}
+
+/// Lowercase letters and digits only, so that spacing and dashes don't matter
+pub(crate) fn key(name:&str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+impl City {
+    pub fn is_called(&self, name:&str) -> bool {
+        let name = key(name);
+        !name.is_empty() && (key(&self.name) == name || self.aliases.iter().any(|alias| key(alias) == name))
+    }
+
+    /// Great circle distance in km
+    pub fn distance_km(&self, other:&City) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+}
+
+
+/// Cities are shared, clones of a table are cheap
+#[derive(Debug, Clone)]
+pub struct GeoTable {
+    cities:Rc<Vec<City>>,
+}
+
+impl GeoTable {
+    /// Table shipped with the program
+    pub fn bundled() -> Self {
+        GeoTable {
+            cities:Rc::new(serde_json::from_str(CITIES).expect("cities.json is not a valid city table"))
+        }
+    }
+
+    /// City by its name or one of its aliases, without case
+    pub fn find(&self, name:&str) -> Option<&City> {
+        self.cities.iter().find(|city| city.is_called(name))
+    }
+
+    /// Name of the city as in the table, the trimmed input if the city is unknown
+    pub fn normalize(&self, name:&str) -> String {
+        match self.find(name) {
+            Some(city) => city.name.clone(),
+            None => name.trim().to_string()
+        }
+    }
+
+    /// Cities at most `km` away from `origin`, `origin` included
+    pub fn within(&self, origin:&City, km:f64) -> Vec<&City> {
+        self.cities.iter().filter(|city| origin.distance_km(city) <= km).collect()
+    }
+}
+
+
+/// Distances from the city of a person to the city of candidates
+pub struct Distance {
+    table:GeoTable,
+    origin:City,
+}
+
+impl Distance {
+    /// None if the city is not in the table
+    pub fn from(table:GeoTable, city:&str) -> Option<Self> {
+        let origin = table.find(city)?.clone();
+        Some(Distance {
+            table,
+            origin
+        })
+    }
+
+    /// Distance in km to the city, None if the city is not in the table
+    pub fn to(&self, city:&str) -> Option<f32> {
+        self.table.find(city).map(|city| self.origin.distance_km(city) as f32)
+    }
+
+    /// Every name and alias of the cities at most `km` away
+    pub fn names_within(&self, km:f32) -> Vec<String> {
+        self.table.within(&self.origin, km as f64).iter()
+            .flat_map(|city| std::iter::once(city.name.clone()).chain(city.aliases.iter().cloned()))
+            .collect()
+    }
+}
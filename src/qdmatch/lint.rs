@@ -0,0 +1,501 @@
+//  Lint
+//  Checks of a rules file before it is used for matching, every finding
+//  with the line of the rule file it is about.
+
+use std::fmt;
+use std::collections::{HashMap, HashSet};
+use super::dictionary::Dictionary;
+use super::rules::{categorize, Category, MatchRule, QdList, QdPolicy, Rule, RuleKind, ListType, ANY_ROW};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The rules can't be used like this
+    Error,
+
+    /// The rules work, but likely not as intended
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity:Severity,
+
+    /// Line in the rules file, starting at 1
+    pub line:Option<usize>,
+    pub message:String,
+}
+
+impl Diagnostic {
+    fn error(line:Option<usize>, message:String) -> Self {
+        Diagnostic {
+            severity:Severity::Error,
+            line,
+            message
+        }
+    }
+
+    fn warning(line:Option<usize>, message:String) -> Self {
+        Diagnostic {
+            severity:Severity::Warning,
+            line,
+            message
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+
+        match self.line {
+            Some(line) => write!(f, "line {}: {}: {}", line, severity, self.message),
+            None => write!(f, "{}: {}", severity, self.message)
+        }
+    }
+}
+
+
+/// Fields of a person the rules can refer to
+const KNOWN_FIELDS:[&'static str; 14] = [
+    "qid", "name", "gender", "age", "education", "verbal_ability", "verbal", "city",
+    "profession", "seeking", "email", "phone", "languages", "response_rating"
+];
+
+/// Fields compared as numbers
+const NUMERIC_FIELDS:[&'static str; 2] = ["age", "response_rating"];
+
+/// Fields holding a list of values
+const LIST_FIELDS:[&'static str; 1] = ["languages"];
+
+
+/// Where things are in the text of the rules file
+struct Lines<'a> {
+    text:&'a str,
+}
+
+impl<'a> Lines<'a> {
+    fn line_at(&self, offset:usize) -> usize {
+        self.text[..offset].matches('\n').count() + 1
+    }
+
+    /// Offset of the `nth` occurrence of the key, from 0
+    fn key_offset(&self, key:&str, nth:usize) -> Option<usize> {
+        let needle = format!("\"{}\"", key);
+        self.text.match_indices(&needle[..]).map(|(offset, _)| offset).nth(nth)
+    }
+
+    fn key(&self, key:&str, nth:usize) -> Option<usize> {
+        self.key_offset(key, nth).map(|offset| self.line_at(offset))
+    }
+
+    /// Offsets of the objects directly inside the top level array
+    fn array_items(&self) -> Vec<usize> {
+        let mut items:Vec<usize> = Vec::new();
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        for (offset, c) in self.text.char_indices() {
+            if in_string {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match c {
+                '"' => in_string = true,
+                '[' | '{' => {
+                    if c == '{' && depth == 1 {
+                        items.push(offset);
+                    }
+                    depth += 1;
+                }
+                ']' | '}' => depth -= 1,
+                _ => {}
+            }
+        }
+
+        items
+    }
+
+    /// Line of the key holding a value starting with `opening`, within the offsets.
+    /// Role names are also values of `role_priority`, only the key of the role is wanted
+    fn member(&self, key:&str, opening:char, span:(usize, usize)) -> Option<usize> {
+        let needle = format!("\"{}\"", key);
+        let text = &self.text[span.0..span.1];
+        text.match_indices(&needle[..])
+            .find(|(found, _)| {
+                let rest = text[found + needle.len()..].trim_start();
+                rest.starts_with(':') && rest[1..].trim_start().starts_with(opening)
+            })
+            .map(|(found, _)| self.line_at(span.0 + found))
+    }
+}
+
+
+/// Values found in the database by field, to find rule values which never occur
+pub type KnownValues = HashMap<String, HashSet<String>>;
+
+/// Lowercase canonical value, its category if the field has categories
+fn comparable(dictionary:&Dictionary, categories:&[Category], field:&str, value:&str) -> String {
+    categorize(categories, &dictionary.normalize(field, value)).to_lowercase()
+}
+
+/// Value of the rules which no person in the database has
+fn unknown_value(values:Option<&KnownValues>, dictionary:&Dictionary, categories:&[Category], field:&str, value:&str) -> bool {
+    let known = match values.and_then(|values| values.get(field)) {
+        Some(known) => known,
+        None => return false
+    };
+
+    let value = comparable(dictionary, categories, field, value);
+    !known.iter().any(|known| comparable(dictionary, categories, field, known) == value)
+}
+
+
+/// Check the text of a rules file in either format.
+/// Values are only checked against the database when `values` are given
+pub fn check(text:&str, values:Option<&KnownValues>) -> Vec<Diagnostic> {
+    let json:serde_json::Value = match serde_json::from_str(text) {
+        Ok(json) => json,
+        Err(e) => return vec![Diagnostic::error(Some(e.line()), format!("not valid json, {}", e))]
+    };
+
+    //Parse the text again, json values don't know their line
+    if json.is_array() {
+        match serde_json::from_str::<Vec<QdPolicy>>(text) {
+            Ok(policies) => check_qd_rules(&policies, &Lines { text }, values),
+            Err(e) => vec![Diagnostic::error(Some(e.line()), format!("not a valid qd_rules policy list, {}", e))]
+        }
+    }else{
+        match serde_json::from_str::<MatchRule>(text) {
+            Ok(rules) => check_rules(&rules, &Lines { text }, values),
+            Err(e) => vec![Diagnostic::error(Some(e.line()), format!("not a valid rules file, {}", e))]
+        }
+    }
+}
+
+
+fn check_rules(rules:&MatchRule, lines:&Lines, values:Option<&KnownValues>) -> Vec<Diagnostic> {
+    let mut diagnostics:Vec<Diagnostic> = Vec::new();
+    let dictionary = Dictionary::bundled();
+
+    //Rows are counted across all the rules, to find the line of each
+    let mut row_index = 0;
+    let mut seen:HashMap<i32, usize> = HashMap::new();
+
+    for (index, rule) in rules.rules.iter().enumerate() {
+        let line = lines.key("rule_id", index);
+
+        if let Some(first) = seen.get(&rule.rule_id) {
+            diagnostics.push(Diagnostic::error(line,
+                format!("rule id {} is used again, first by the rule on line {}", rule.rule_id, lines.key("rule_id", *first).unwrap_or_default())));
+        }else{
+            seen.insert(rule.rule_id, index);
+        }
+
+        if !KNOWN_FIELDS.contains(&&rule.rule_field[..]) {
+            diagnostics.push(Diagnostic::error(line, format!("rule {} refers to unknown field '{}'", rule.rule_id, rule.rule_field)));
+        }
+        if rule.self_field() != rule.rule_field && !KNOWN_FIELDS.contains(&rule.self_field()) {
+            diagnostics.push(Diagnostic::error(line, format!("rule {} refers to unknown field '{}'", rule.rule_id, rule.self_field())));
+        }
+
+        let numeric = NUMERIC_FIELDS.contains(&&rule.rule_field[..]);
+        match rule.rule_kind {
+            RuleKind::Offset if !numeric => {
+                diagnostics.push(Diagnostic::error(line, format!("rule {} is an offset rule on '{}', which is not a number", rule.rule_id, rule.rule_field)));
+            }
+
+            RuleKind::Ordered | RuleKind::Set | RuleKind::Matrix | RuleKind::Overlap if numeric => {
+                diagnostics.push(Diagnostic::error(line, format!("rule {} compares '{}' as text, it is a number", rule.rule_id, rule.rule_field)));
+            }
+
+            RuleKind::Overlap if !LIST_FIELDS.contains(&&rule.rule_field[..]) => {
+                diagnostics.push(Diagnostic::error(line, format!("rule {} is an overlap rule on '{}', which is not a list", rule.rule_id, rule.rule_field)));
+            }
+
+            _ => {}
+        }
+
+        if rule.rule_kind == RuleKind::Overlap {
+            if rule.rule_data.len() > 0 {
+                diagnostics.push(Diagnostic::warning(line, format!("rule {} is an overlap rule, its rule_data is not used", rule.rule_id)));
+            }
+        }else if rule.rule_data.is_empty() {
+            diagnostics.push(Diagnostic::warning(line, format!("rule {} has no rows, it never applies", rule.rule_id)));
+        }
+
+        let self_categories = rules.categories_for(rule.self_field());
+        let categories = rules.categories_for(&rule.rule_field);
+        for node in &rule.rule_data {
+            let line = lines.key("r_self", row_index);
+            row_index += 1;
+            let row = format!("row '{}' of rule {}", node.r_self, rule.rule_id);
+
+            match rule.rule_kind {
+                RuleKind::Ordered | RuleKind::Set | RuleKind::Matrix => {
+                    if node.r_seek_string.is_empty() {
+                        diagnostics.push(Diagnostic::warning(line, format!("{} has an empty r_seek_string, it scores nobody", row)));
+                    }
+
+                    if rule.rule_kind == RuleKind::Matrix && node.r_seek_float.len() != node.r_seek_string.len() {
+                        diagnostics.push(Diagnostic::error(line,
+                            format!("{} has {} values but {} scores", row, node.r_seek_string.len(), node.r_seek_float.len())));
+                    }else if rule.rule_kind != RuleKind::Matrix && node.r_seek_float.len() > 0 {
+                        diagnostics.push(Diagnostic::warning(line, format!("{} has an r_seek_float, text rules don't use it", row)));
+                    }
+                }
+
+                RuleKind::Offset => {
+                    if node.r_seek_string.len() > 0 {
+                        diagnostics.push(Diagnostic::error(line, format!("{} has an r_seek_string, offset rules need r_seek_float", row)));
+                    }else if node.r_seek_float.is_empty() && node.r_tolerance.is_none() {
+                        diagnostics.push(Diagnostic::warning(line, format!("{} has neither r_seek_float nor r_tolerance, it scores nobody", row)));
+                    }
+                }
+
+                RuleKind::Distance => {
+                    if node.r_tolerance.is_none() {
+                        diagnostics.push(Diagnostic::error(line, format!("{} needs an r_tolerance with the radius", row)));
+                    }
+                }
+
+                RuleKind::Overlap => {}
+            }
+
+            if node.r_self != ANY_ROW && unknown_value(values, &dictionary, self_categories, rule.self_field(), &node.r_self) {
+                diagnostics.push(Diagnostic::warning(line, format!("{} is for '{}' of {}, which nobody in the database has", row, node.r_self, rule.self_field())));
+            }
+
+            if rule.rule_kind != RuleKind::Offset && rule.rule_kind != RuleKind::Distance {
+                for seek in &node.r_seek_string {
+                    if unknown_value(values, &dictionary, categories, &rule.rule_field, seek) {
+                        diagnostics.push(Diagnostic::warning(line, format!("{} seeks '{}' of {}, which nobody in the database has", row, seek, rule.rule_field)));
+                    }
+                }
+            }
+        }
+    }
+
+    for (index, policy) in rules.policy.iter().enumerate() {
+        let line = lines.key("p_self", index);
+        let name = format!("policy {} -> {}", policy.p_self, policy.p_partner);
+
+        let mut used:Vec<&Rule> = Vec::new();
+        for rule_id in &policy.p_rules {
+            match rules.rule(*rule_id) {
+                Some(rule) => used.push(rule),
+                None => diagnostics.push(Diagnostic::error(line, format!("{} refers to rule {}, which doesn't exist", name, rule_id)))
+            }
+        }
+
+        for rule in rules.rules.iter().filter(|rule| rule.rule_required) {
+            if !policy.p_rules.contains(&rule.rule_id) {
+                diagnostics.push(Diagnostic::warning(line, format!("{} doesn't use rule {}, which is required", name, rule.rule_id)));
+            }
+        }
+
+        for (position, first) in used.iter().enumerate() {
+            for second in &used[position + 1..] {
+                if first.rule_id != second.rule_id && first.rule_field == second.rule_field {
+                    diagnostics.push(Diagnostic::warning(line,
+                        format!("{} uses rules {} and {} which both score {}", name, first.rule_id, second.rule_id, first.rule_field)));
+                }
+            }
+        }
+    }
+
+    //Rules on the same field with rows for the same persons, likely copies of each other
+    for (position, first) in rules.rules.iter().enumerate() {
+        for (index, second) in rules.rules.iter().enumerate().skip(position + 1) {
+            if first.rule_id == second.rule_id || first.rule_field != second.rule_field || first.self_field() != second.self_field() {
+                continue;
+            }
+
+            let shared:Vec<&str> = first.rule_data.iter()
+                .filter(|node| second.rule_data.iter().any(|other| other.r_self.to_lowercase() == node.r_self.to_lowercase()))
+                .map(|node| &node.r_self[..])
+                .collect();
+            if shared.len() > 0 {
+                diagnostics.push(Diagnostic::warning(lines.key("rule_id", index),
+                    format!("rules {} and {} both score {} for {}", first.rule_id, second.rule_id, first.rule_field, shared.join(", "))));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+
+fn check_qd_rules(policies:&Vec<QdPolicy>, lines:&Lines, values:Option<&KnownValues>) -> Vec<Diagnostic> {
+    let mut diagnostics:Vec<Diagnostic> = Vec::new();
+    let dictionary = Dictionary::bundled();
+    let items = lines.array_items();
+
+    for (index, policy) in policies.iter().enumerate() {
+        let start = items.get(index).cloned().unwrap_or_default();
+        let span = (start, items.get(index + 1).cloned().unwrap_or(lines.text.len()));
+        let line = Some(lines.line_at(start));
+        let name = format!("policy {} -> {}", policy.first, policy.second);
+
+        if policy.roles.is_empty() {
+            diagnostics.push(Diagnostic::warning(line, format!("{} has no roles", name)));
+        }
+
+        for role in &policy.role_priority {
+            if !policy.roles.contains_key(role) {
+                diagnostics.push(Diagnostic::warning(lines.member("role_priority", '[', span), format!("{} lists '{}' in role_priority, it has no such role", name, role)));
+            }
+        }
+
+        for (field, role) in &policy.roles {
+            let line = lines.member(field, '{', span);
+            let numeric = NUMERIC_FIELDS.contains(&&field[..]);
+
+            if !KNOWN_FIELDS.contains(&&field[..]) {
+                diagnostics.push(Diagnostic::error(line, format!("{} has a role for unknown field '{}'", name, field)));
+            }
+
+            match (role.list_type, &role.list) {
+                (ListType::OneD, QdList::Text(list)) => {
+                    if numeric && list.len() > 0 {
+                        diagnostics.push(Diagnostic::error(line, format!("role '{}' of {} lists text, {} is a number", field, name, field)));
+                    }
+                    if list.is_empty() {
+                        diagnostics.push(Diagnostic::warning(line, format!("role '{}' of {} has an empty list, it scores nobody", field, name)));
+                    }
+
+                    for value in list.iter().filter(|value| value.to_lowercase() != "same") {
+                        if unknown_value(values, &dictionary, &[], field, value) {
+                            diagnostics.push(Diagnostic::warning(line, format!("role '{}' of {} seeks '{}', which nobody in the database has", field, name, value)));
+                        }
+                    }
+                }
+
+                (ListType::OneD, QdList::Number(list)) => {
+                    if !numeric {
+                        diagnostics.push(Diagnostic::error(line, format!("role '{}' of {} lists numbers, {} is text", field, name, field)));
+                    }
+                    if list.is_empty() && role.tolerance.is_none() {
+                        diagnostics.push(Diagnostic::warning(line, format!("role '{}' of {} has an empty list, it scores nobody", field, name)));
+                    }
+                }
+
+                (ListType::TwoD, QdList::Table(rows)) => {
+                    for row in rows {
+                        if row.partner.is_empty() {
+                            diagnostics.push(Diagnostic::warning(line, format!("row '{}' of role '{}' of {} has an empty partner list", row.r_self, field, name)));
+                        }
+
+                        for value in std::iter::once(&row.r_self).chain(row.partner.iter()) {
+                            if unknown_value(values, &dictionary, &[], field, value) {
+                                diagnostics.push(Diagnostic::warning(line, format!("role '{}' of {} has '{}', which nobody in the database has", field, name, value)));
+                            }
+                        }
+                    }
+                }
+
+                (ListType::TwoD, QdList::Text(list)) if list.is_empty() => {
+                    diagnostics.push(Diagnostic::warning(line, format!("role '{}' of {} has an empty list, it scores nobody", field, name)));
+                }
+
+                _ => {
+                    diagnostics.push(Diagnostic::error(line, format!("list of role '{}' of {} doesn't match its list_type", field, name)));
+                }
+            }
+        }
+    }
+
+    diagnostics
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line and message of every diagnostic
+    fn found(text:&str) -> Vec<(Option<usize>, String)> {
+        check(text, None).into_iter().map(|diagnostic| (diagnostic.line, diagnostic.message)).collect()
+    }
+
+    const RULES:&'static str = r#"{
+    "rules":[
+        {
+            "rule_name":"education",
+            "rule_id":1,
+            "rule_field":"education",
+            "rule_data":[{"r_self":"*", "r_seek_string":["Graduation"]}]
+        },
+        {
+            "rule_name":"hobby",
+            "rule_id":1,
+            "rule_field":"hobby",
+            "rule_data":[{"r_self":"*", "r_seek_string":["Chess"]}]
+        }
+    ],
+    "policy":[
+        {"p_self":"Female", "p_partner":"Male", "p_rules":[1, 7]}
+    ]
+}"#;
+
+    #[test]
+    fn rules_are_checked_at_their_line() {
+        let found = found(RULES);
+        assert!(found.contains(&(Some(11), String::from("rule id 1 is used again, first by the rule on line 5"))), "{:?}", found);
+        assert!(found.contains(&(Some(11), String::from("rule 1 refers to unknown field 'hobby'"))), "{:?}", found);
+        assert!(found.contains(&(Some(17), String::from("policy Female -> Male refers to rule 7, which doesn't exist"))), "{:?}", found);
+    }
+
+    #[test]
+    fn type_mismatches_point_at_their_line() {
+        let text = RULES.replacen(r#""rule_id":1,"#, r#""rule_id":"one","#, 1);
+        let found = check(&text, None);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].is_error());
+        assert_eq!(found[0].line, Some(5));
+        assert!(found[0].message.starts_with("not a valid rules file"), "{}", found[0].message);
+    }
+
+    #[test]
+    fn roles_are_checked_at_their_key() {
+        let text = r#"[
+    {
+        "first":"male",
+        "second":"female",
+        "role_priority":["gender", "hobby", "age"],
+
+        "gender":{
+            "type":"cut",
+            "list_type":"1d",
+            "list":[]
+        },
+
+        "age":{
+            "type":"cut",
+            "list_type":"1d",
+            "list":["young"]
+        }
+    }
+]"#;
+        let found = found(text);
+        assert_eq!(found, vec![
+            (Some(5), String::from("policy male -> female lists 'hobby' in role_priority, it has no such role")),
+            (Some(13), String::from("role 'age' of policy male -> female lists text, age is a number")),
+            (Some(7), String::from("role 'gender' of policy male -> female has an empty list, it scores nobody")),
+        ]);
+    }
+}
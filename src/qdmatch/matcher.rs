@@ -1,16 +1,19 @@
 
 use crate::db::db_models::DocPerson;
-use super::model::CandidatePerson;
-use bson::{doc, Document};
+use crate::db::db_store::{Filter, Pattern};
+use super::model::{CandidatePerson, ScoreDetail};
 use serde::{Deserialize, Serialize};
-use super::rules::MatchRule;
-use std::fs::File;
+use super::dictionary::{Dictionary, Synonyms, normalize};
+use super::geo::{Distance, GeoTable};
+use super::lint;
+use super::rules::{ANY_ROW, RuleBook, Aggregation, Tolerance, Curve, Category, categorize, ResponseBoost, MatchRule, Rule, RuleKind, QdPolicy, QdRole, QdList, ListType, RoleType};
+use std::fmt;
+use std::collections::{HashMap, HashSet};
 use std::io::Read;
-use rand::thread_rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
 
-const RULE_ID_AGE:i32 = 1;
-
 //Macher has to be implemented by all Kind of Matchers
 trait Matcher<T:PartialEq> {
     fn getList(&self) -> &Vec<T>;
@@ -37,12 +40,34 @@ trait Matcher<T:PartialEq> {
 
         weight
     }
+
+    /// Lowest and highest value scoring full, None if values are scored by their position
+    fn band(&self) -> Option<(f32, f32)> {
+        None
+    }
 }
 
 
 pub enum MatchError {
     JsonError,
     NoRulesFound,
+    InvalidRule(String),
+    FileError(String),
+
+    /// Gender and seeking of a person no policy is written for
+    NoPolicy(String, String),
+}
+
+impl fmt::Display for MatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchError::JsonError => write!(f, "rules are not valid json"),
+            MatchError::NoRulesFound => write!(f, "no rules apply"),
+            MatchError::InvalidRule(reason) => write!(f, "{}", reason),
+            MatchError::FileError(reason) => write!(f, "{}", reason),
+            MatchError::NoPolicy(gender, seeking) => write!(f, "no policy for {} seeking {}", gender, seeking)
+        }
+    }
 }
 
 impl From<serde_json::error::Error> for MatchError {
@@ -53,16 +78,6 @@ impl From<serde_json::error::Error> for MatchError {
 
 
 
-fn default_gender() -> String {
-    "-".to_string()
-}
-
-fn default_1d_list() -> String {
-    "1d".to_string()
-}
-
-
-
 pub struct MatcherFilter<T> {
     priority_list: Vec<T>
 }
@@ -85,165 +100,1428 @@ impl<T:Clone+PartialEq> Matcher<T> for MatcherFilter<T> {
 
 
 
+/// Scores only membership, every entry of the list is equally preferred
+pub struct MatcherSet<T> {
+    set_list: Vec<T>
+}
+
+impl<T:Clone> MatcherSet<T> {
+    fn new(seeks:&Vec<T>) -> Option<Self> {
+        Some(Self {
+            set_list:seeks.to_vec()
+        })
+    }
+}
+
+impl<T:Clone+PartialEq> Matcher<T> for MatcherSet<T> {
+    fn getList(&self) ->&Vec<T> {
+        &self.set_list
+    }
+
+    fn calculate(&self, value:&T) -> f32 {
+        if self.set_list.contains(value) {
+            return 100.0;
+        }
+
+        0.0
+    }
+}
+
+
+/// Every entry of the list has its own score, like a row of a compatibility matrix
+pub struct MatcherMatrix<T> {
+    seek_list: Vec<T>,
+    scores: Vec<f32>,
+}
+
+impl<T:Clone> MatcherMatrix<T> {
+    fn new(seeks:&Vec<T>, scores:&Vec<f32>) -> Option<Self> {
+        Some(Self {
+            seek_list:seeks.to_vec(),
+            scores:scores.to_vec()
+        })
+    }
+}
+
+impl<T:Clone+PartialEq> Matcher<T> for MatcherMatrix<T> {
+    fn getList(&self) ->&Vec<T> {
+        &self.seek_list
+    }
+
+    //Entries without a score are not compatible
+    fn calculate(&self, value:&T) -> f32 {
+        match self.seek_list.iter().position(|seek| seek == value) {
+            Some(index) => self.scores.get(index).cloned().unwrap_or_default().max(0.0).min(100.0),
+            None => 0.0
+        }
+    }
+}
+
+
+/// Scores full inside a band of values, decays outside of it
+pub struct MatcherRange {
+    /// Lowest and highest value of the band
+    band_list: Vec<f32>,
+    falloff: f32,
+    curve: Curve,
+}
+
+impl MatcherRange {
+    fn new(min:f32, max:f32, tolerance:&Tolerance) -> Option<Self> {
+        Some(Self {
+            band_list:vec![min, max],
+            falloff:tolerance.falloff,
+            curve:tolerance.curve
+        })
+    }
+
+    /// Distance outside the band which still scores
+    fn reach(&self) -> f32 {
+        match self.curve {
+            Curve::Linear => self.falloff,
+            Curve::Gaussian => 3.0 * self.falloff
+        }
+    }
+}
+
+impl Matcher<f32> for MatcherRange {
+    fn getList(&self) ->&Vec<f32> {
+        &self.band_list
+    }
+
+    fn band(&self) -> Option<(f32, f32)> {
+        Some((self.band_list[0], self.band_list[1]))
+    }
+
+    fn calculate(&self, value:&f32) -> f32 {
+        let (min, max) = (self.band_list[0], self.band_list[1]);
+        if *value >= min && *value <= max {
+            return 100.0;
+        }
+
+        let distance = if *value < min { min - value } else { value - max };
+        if self.falloff <= 0.0 || distance >= self.reach() {
+            return 0.0;
+        }
+
+        match self.curve {
+            Curve::Linear => 100.0 * (1.0 - distance / self.falloff),
+            Curve::Gaussian => 100.0 * (-0.5 * (distance / self.falloff).powi(2)).exp()
+        }
+    }
+}
+
+
+/// Matcher built from a rule, compares against string or numeric field values
+enum FieldMatcherKind {
+    Text(Box<dyn Matcher<String>>),
+    Number(Box<dyn Matcher<f32>>),
+}
+
+/// A matcher bound to the profile field of the candidate it scores
+struct FieldMatcher {
+    /// Rule or role this matcher was built from
+    name:String,
+    field:String,
+    matcher:FieldMatcherKind,
+
+    /// Candidates this matcher doesn't score are dropped
+    cut:bool,
+
+    /// Candidates without a value for the field score zero instead of being scored without it
+    required:bool,
+
+    /// Share of this matcher in the match score
+    weight:f32,
+
+    /// Numeric values this far outside of the list still score
+    reach:f32,
+
+    /// Text values are compared by their canonical value
+    synonyms:Vec<Synonyms>,
+
+    /// Text values are compared by their category when given
+    categories:Vec<Category>,
+
+    /// Cities are compared by their distance to this one when given
+    distance:Option<Distance>,
+}
+
+/// Text values are compared without case
+fn lowercase_list(list:&Vec<String>) -> Vec<String> {
+    list.iter().map(|value| value.to_lowercase()).collect()
+}
+
+/// Canonical values of a list, without case
+fn seek_list(list:&Vec<String>, synonyms:&[Synonyms]) -> Vec<String> {
+    list.iter().map(|value| normalize(synonyms, value).to_lowercase()).collect()
+}
+
+/// Own values of a list field without blanks and repeats, first one wins
+fn own_list(values:&Vec<String>) -> Vec<String> {
+    let mut list:Vec<String> = Vec::new();
+    for value in lowercase_list(values) {
+        let value = value.trim().to_string();
+        if !value.is_empty() && !list.contains(&value) {
+            list.push(value);
+        }
+    }
+    list
+}
+
+/// Offsets are relative to the person's own value of the field, None if the person has none
+fn offset_list(person:&DocPerson, field:&str, offsets:&Vec<f32>) -> Option<Vec<f32>> {
+    let self_value = person.field_f32(field)?;
+    Some(offsets.iter().map(|x| x + self_value).collect())
+}
+
+/// Matcher of offsets to the person's own value, by range when a tolerance is given.
+/// None if the person has no value of their own to offset
+fn offset_matcher(person:&DocPerson, field:&str, offsets:&Vec<f32>, tolerance:&Option<Tolerance>) -> Option<(FieldMatcherKind, f32)> {
+    match tolerance {
+        Some(tolerance) => {
+            let band = offset_list(person, field, &vec![tolerance.band.0, tolerance.band.1])?;
+            let matcher = MatcherRange::new(band[0].min(band[1]), band[0].max(band[1]), tolerance)?;
+            let reach = matcher.reach();
+            Some((FieldMatcherKind::Number(Box::new(matcher)), reach))
+        }
+
+        None => {
+            let matcher = MatcherFilter::new(&offset_list(person, field, offsets)?)?;
+            Some((FieldMatcherKind::Number(Box::new(matcher)), 0.0))
+        }
+    }
+}
+
+/// Offset and distance rules are relative to the person's own value,
+/// they don't apply to a person without one
+fn lacks_own_value(rule:&Rule, person:&DocPerson) -> bool {
+    match rule.rule_kind {
+        RuleKind::Offset => person.field_f32(&rule.rule_field).is_none(),
+        RuleKind::Distance => person.field(&rule.rule_field).map_or(true, |value| value.trim().is_empty()),
+        _ => false
+    }
+}
+
+impl FieldMatcher {
+
+    /// Build the matcher of the rule for the person, None if the rule has no row for this person
+    fn new(rule:&Rule, rules:&MatchRule, lookups:&Lookups, person:&DocPerson) -> Option<Self> {
+        let dictionary = &lookups.dictionary;
+        let synonyms = dictionary.synonyms_for(&rule.rule_field).to_vec();
+
+        //Overlap seeks the person's own values, there is no row to pick
+        if rule.rule_kind == RuleKind::Overlap {
+            let own = person.field_list(rule.self_field())?;
+            if own.iter().all(|value| value.trim().is_empty()) {
+                return None;
+            }
+
+            return Some(FieldMatcher {
+                name:rule.rule_name.clone(),
+                field:rule.rule_field.clone(),
+                matcher:FieldMatcherKind::Text(Box::new(MatcherFilter::new(&own_list(own))?)),
+                cut:rule.rule_cut,
+                required:rule.rule_required || rule.rule_cut,
+                weight:rule.rule_weight,
+                reach:0.0,
+                synonyms,
+                categories:Vec::new(),
+                distance:None
+            });
+        }
+
+        //Rows may be written for the category of the person's value
+        let self_value = match person.field(rule.self_field()) {
+            Some(value) => value,
+            None => return None
+        };
+        let self_synonyms = dictionary.synonyms_for(rule.self_field());
+        let node = rule.data_for(self_value, self_synonyms)
+            .or_else(|| rule.data_for(&categorize(rules.categories_for(rule.self_field()), self_value), self_synonyms))
+            .or_else(|| rule.data_for(ANY_ROW, self_synonyms))?;
+        let cut = rule.rule_cut;
+        let mut reach = 0.0;
+        let mut distance = None;
+        let matcher = match rule.rule_kind {
+            RuleKind::Ordered => {
+                FieldMatcherKind::Text(Box::new(MatcherFilter::new(&seek_list(&node.r_seek_string, &synonyms))?))
+            }
+
+            RuleKind::Set => {
+                FieldMatcherKind::Text(Box::new(MatcherSet::new(&seek_list(&node.r_seek_string, &synonyms))?))
+            }
+
+            RuleKind::Offset => {
+                let (matcher, offset_reach) = offset_matcher(person, &rule.rule_field, &node.r_seek_float, &node.r_tolerance)?;
+                reach = offset_reach;
+                matcher
+            }
+
+            RuleKind::Matrix => {
+                FieldMatcherKind::Text(Box::new(MatcherMatrix::new(&seek_list(&node.r_seek_string, &synonyms), &node.r_seek_float)?))
+            }
+
+            //Band is in km from the person's own city
+            RuleKind::Distance => {
+                let tolerance = node.r_tolerance.as_ref()?;
+                distance = Some(Distance::from(lookups.cities.clone(), person.field(&rule.rule_field)?)?);
+
+                let matcher = MatcherRange::new(tolerance.band.0.min(tolerance.band.1), tolerance.band.0.max(tolerance.band.1), tolerance)?;
+                reach = matcher.reach();
+                FieldMatcherKind::Number(Box::new(matcher))
+            }
+
+            RuleKind::Overlap => return None
+        };
+
+        Some(FieldMatcher {
+            name:rule.rule_name.clone(),
+            field:rule.rule_field.clone(),
+            matcher,
+            cut,
+            required:rule.rule_required || cut,
+            weight:rule.rule_weight,
+            reach,
+            synonyms,
+            categories:rules.categories_for(&rule.rule_field).to_vec(),
+            distance
+        })
+    }
+
+    /// Build the matcher of a `qd_rules.json` role for the person,
+    /// None if the role doesn't apply to this person
+    fn from_role(field:&str, role:&QdRole, dictionary:&Dictionary, person:&DocPerson) -> Result<Option<Self>, MatchError> {
+        let self_value = person.field(field);
+        let synonyms = dictionary.synonyms_for(field).to_vec();
+        let mut reach = 0.0;
+
+        let matcher = match (role.list_type, &role.list) {
+            (ListType::OneD, QdList::Text(list)) => {
+                //"same" seeks the person's own value
+                let mut priority_list:Vec<String> = Vec::new();
+                for value in list {
+                    if value.to_lowercase() == "same" {
+                        //All own values of a list field, in the person's order
+                        if let Some(own) = person.field_list(field) {
+                            priority_list.extend(own_list(own));
+                            continue;
+                        }
+
+                        match self_value {
+                            Some(self_value) => priority_list.push(normalize(&synonyms, self_value).to_lowercase()),
+                            None => return Ok(None)
+                        }
+                    }else{
+                        priority_list.push(normalize(&synonyms, value).to_lowercase());
+                    }
+                }
+
+                FieldMatcherKind::Text(Box::new(MatcherFilter::new(&priority_list).unwrap()))
+            }
+
+            //Person without a value of their own has nothing to offset, the role doesn't apply
+            (ListType::OneD, QdList::Number(list)) => {
+                let (matcher, offset_reach) = match offset_matcher(person, field, list, &role.tolerance) {
+                    Some(offset) => offset,
+                    None => return Ok(None)
+                };
+                reach = offset_reach;
+                matcher
+            }
+
+            (ListType::TwoD, QdList::Table(rows)) => {
+                let self_value = match self_value {
+                    Some(value) => normalize(&synonyms, value).to_lowercase(),
+                    None => return Ok(None)
+                };
+
+                match rows.iter().find(|row| normalize(&synonyms, &row.r_self).to_lowercase() == self_value) {
+                    Some(row) => {
+                        FieldMatcherKind::Text(Box::new(MatcherFilter::new(&seek_list(&row.partner, &synonyms)).unwrap()))
+                    }
+
+                    //No row for this person
+                    None => return Ok(None)
+                }
+            }
+
+            //Empty 2d list has nothing for anyone
+            (ListType::TwoD, QdList::Text(list)) if list.is_empty() => {
+                return Ok(None);
+            }
+
+            _ => {
+                return Err(MatchError::InvalidRule(format!("list of role '{}' doesn't match its list_type", field)));
+            }
+        };
+
+        Ok(Some(FieldMatcher {
+            name:field.to_string(),
+            field:field.to_string(),
+            matcher,
+            cut:role.role_type == RoleType::Cut,
+            required:role.role_type == RoleType::Cut,
+            weight:role.weight,
+            reach,
+            synonyms,
+            categories:Vec::new(),
+            distance:None
+        }))
+    }
+
+    /// Cut matcher for the gender a policy is for, None when the policy is for anyone.
+    /// It has no weight, the policy already decided on gender
+    fn seeking(partner:&str, dictionary:&Dictionary) -> Option<Self> {
+        let partner = seeking_of(partner, dictionary);
+        if partner == SEEKING_ANY {
+            return None;
+        }
+
+        let synonyms = dictionary.synonyms_for("gender").to_vec();
+        Some(FieldMatcher {
+            name:String::from("seeking"),
+            field:String::from("gender"),
+            matcher:FieldMatcherKind::Text(Box::new(MatcherSet::new(&vec![normalize(&synonyms, &partner).to_lowercase()])?)),
+            cut:true,
+            required:true,
+            weight:0.0,
+            reach:0.0,
+            synonyms,
+            categories:Vec::new(),
+            distance:None
+        })
+    }
+
+    /// Lowercase non blank canonical values of the candidate for the field, every entry
+    /// for list fields, their categories if the field has any
+    fn values(&self, candidate:&CandidatePerson) -> Vec<String> {
+        candidate.values(&self.field).iter()
+            .filter(|value| !value.trim().is_empty())
+            .map(|value| categorize(&self.categories, &normalize(&self.synonyms, value)))
+            .collect()
+    }
+
+    /// Numeric value of the candidate for the field, the distance in km for cities
+    fn number(&self, candidate:&CandidatePerson) -> Option<f32> {
+        match &self.distance {
+            Some(distance) => distance.to(&candidate.field(&self.field)?),
+            None => candidate.field_f32(&self.field)
+        }
+    }
+
+    /// Score of the candidate, None if the candidate has no value for the field
+    fn calculate(&self, candidate:&CandidatePerson) -> Option<f32> {
+        match &self.matcher {
+            //List fields score by their best value
+            FieldMatcherKind::Text(matcher) => {
+                let values = self.values(candidate);
+                if values.is_empty() {
+                    return None;
+                }
+                Some(values.iter().map(|value| matcher.calculate(value)).fold(0.0, f32::max))
+            }
+
+            FieldMatcherKind::Number(matcher) => {
+                let value = self.number(candidate)?;
+                Some(matcher.calculate(&value))
+            }
+        }
+    }
+
+    /// How the candidate was scored by this matcher
+    fn explain(&self, candidate:&CandidatePerson) -> ScoreDetail {
+        let mut band = None;
+        let mut offset = None;
+        let (seeks, position) = match &self.matcher {
+            FieldMatcherKind::Text(matcher) => {
+                let list = matcher.getList();
+                let values = self.values(candidate);
+                (list.clone(), list.iter().position(|seek| values.contains(seek)))
+            }
+
+            FieldMatcherKind::Number(matcher) => {
+                let list = matcher.getList();
+                let value = self.number(candidate);
+                let seeks = list.iter().map(|seek| seek.to_string()).collect();
+
+                //Ranges tell how far the value is from the band instead of a position
+                match matcher.band() {
+                    Some((min, max)) => {
+                        band = Some((min, max));
+                        offset = value.map(|value| if value < min { value - min } else if value > max { value - max } else { 0.0 });
+                        (seeks, None)
+                    }
+
+                    None => (seeks, list.iter().position(|seek| Some(*seek) == value))
+                }
+            }
+        };
+
+        //Cities show their distance next to the name
+        let value = candidate.field(&self.field).unwrap_or_default();
+        let value = match (&self.distance, self.number(candidate)) {
+            (Some(_), Some(km)) => format!("{} ({} km)", value, km.round()),
+            _ => value
+        };
+
+        ScoreDetail {
+            rule:self.name.clone(),
+            field:self.field.clone(),
+            seeks,
+            value,
+            position,
+            band,
+            offset,
+            unit:if self.distance.is_some() { String::from("km") } else { String::new() },
+            score:self.calculate(candidate),
+            weight:self.weight
+        }
+    }
+
+    /// Database condition equivalent to this matcher, None if it doesn't drop candidates.
+    /// Numeric lists become a range, the exact values are checked again by `accepts`
+    fn filter(&self) -> Option<Filter> {
+        if !self.cut {
+            return None;
+        }
+
+        match &self.matcher {
+            //Lists are lowercase, compare without case in database as well
+            FieldMatcherKind::Text(matcher) => {
+                let mut seeks:Vec<Pattern> = matcher.getList().iter()
+                    .map(|value| Pattern::Is(value.clone()))
+                    .collect();
+
+                //Values holding a keyword of a sought category
+                for category in self.categories.iter().filter(|category| matcher.seeks(&category.c_name.trim().to_lowercase())) {
+                    for keyword in &category.c_keywords {
+                        seeks.push(Pattern::Word(keyword.clone()));
+                    }
+                }
+
+                //Documents which are not normalized yet, compared like `Synonyms::contains` does
+                for entry in self.synonyms.iter().filter(|entry| matcher.seeks(&entry.canonical.to_lowercase())) {
+                    seeks.push(Pattern::Key(entry.canonical.clone()));
+                    for synonym in &entry.synonyms {
+                        seeks.push(Pattern::Key(synonym.clone()));
+                    }
+                }
+                Some(Filter::Matches(self.field.clone(), seeks))
+            }
+
+            FieldMatcherKind::Number(matcher) => {
+                let list = matcher.getList();
+                if list.is_empty() {
+                    return Some(Filter::Matches(self.field.clone(), Vec::new()));
+                }
+
+                let min = list.iter().cloned().fold(f32::INFINITY, f32::min) - self.reach;
+                let max = list.iter().cloned().fold(f32::NEG_INFINITY, f32::max) + self.reach;
+
+                //Cities close enough by the table
+                if let Some(distance) = &self.distance {
+                    let seeks:Vec<Pattern> = distance.names_within(max).into_iter()
+                        .map(Pattern::Is)
+                        .collect();
+                    return Some(Filter::Matches(self.field.clone(), seeks));
+                }
+
+                Some(Filter::Between(self.field.clone(), min, max))
+            }
+        }
+    }
+
+    /// False if this is a cut matcher and the candidate is not in its list
+    fn accepts(&self, candidate:&CandidatePerson) -> bool {
+        if !self.cut {
+            return true;
+        }
+
+        match &self.matcher {
+            FieldMatcherKind::Text(matcher) => {
+                self.values(candidate).iter().any(|value| matcher.seeks(value))
+            }
+
+            FieldMatcherKind::Number(matcher) => {
+                match self.number(candidate) {
+                    Some(value) => matcher.seeks(&value),
+                    None => false
+                }
+            }
+        }
+    }
+}
+
+
+/// Canonical seeking of anyone, lowercase
+const SEEKING_ANY:&'static str = "any";
+
+/// Lowercase canonical seeking, the words for anyone are synonyms of `Any` in the dictionary
+fn seeking_of(seeking:&str, dictionary:&Dictionary) -> String {
+    dictionary.normalize("seeking", seeking).to_lowercase()
+}
+
+/// Whether a policy for persons of `gender` seeking `partner` applies to the person.
+/// Persons who didn't say what they seek get the first policy of their gender
+fn serves(person:&DocPerson, gender:&str, partner:&str, dictionary:&Dictionary) -> bool {
+    if dictionary.normalize("gender", &person.gender).to_lowercase() != dictionary.normalize("gender", gender).to_lowercase() {
+        return false;
+    }
+
+    if person.seeking.trim().is_empty() {
+        return true;
+    }
+
+    seeking_of(&person.seeking, dictionary) == seeking_of(partner, dictionary)
+}
+
+/// Error for a person no policy serves
+fn no_policy(person:&DocPerson) -> MatchError {
+    let seeking = if person.seeking.trim().is_empty() { "-" } else { person.seeking.trim() };
+    MatchError::NoPolicy(person.gender.trim().to_string(), seeking.to_string())
+}
+
+
+/// Tables values are compared with, loaded once and shared by every match
+pub struct Lookups {
+    pub dictionary:Dictionary,
+    pub cities:GeoTable,
+}
+
+impl Lookups {
+    /// Tables shipped with the program
+    pub fn bundled() -> Self {
+        Lookups {
+            dictionary:Dictionary::bundled(),
+            cities:GeoTable::bundled()
+        }
+    }
+}
+
+
 pub struct Match {
     person:DocPerson,
-    age:MatcherFilter<f32>,
-    gender:MatcherFilter<String>,
-    education:MatcherFilter<String>,
-    verbal:MatcherFilter<String>
+    matchers:Vec<FieldMatcher>,
+    aggregation:Aggregation,
+
+    /// Qids never to be suggested to the person
+    excluded:HashSet<String>,
+
+    /// Number of persons each candidate already met, for breaking ties
+    prior_matches:HashMap<String, usize>,
+
+    /// Break ties randomly with this seed instead
+    seed:Option<u64>,
+
+    /// Raise or lower scores by the response rating of candidates
+    response:Option<ResponseBoost>,
+
+    /// Candidates scoring less are left out of the results
+    min_score:f32,
+
+    /// Ranked candidates skipped before the results start
+    offset:usize,
+
+    /// Most candidates in the results, all of them when None
+    limit:Option<usize>
 }
 
 impl Match {
 
     pub fn new(person:DocPerson, rule_file:String) -> Result<Option<Self>, MatchError>{
-        //Read policy file and create matcher
-        let mut file = File::open(rule_file).unwrap();
-        let mut json:MatchRule = serde_json::from_reader(file).unwrap();
-
-        let mut age:Option<MatcherFilter<f32>> = None;
-        let mut gender:Option<MatcherFilter<String>> = None;
-        let mut education:Option<MatcherFilter<String>> = None;
-        let mut verbal:Option<MatcherFilter<String>> = None;
-        for policy in json.policy {
-            // TODO : Check for seeking as well
-            if person.gender != policy.p_self {
+        let rules = Self::loadRules(&rule_file)?;
+        Self::withRules(person, &rules, &Lookups::bundled())
+    }
+
+    /// Read the rule file once, to build matchers for many persons
+    /// Rules with errors found by `lint::check` are refused, with the first error as reason
+    pub fn loadRules(rule_file:&String) -> Result<RuleBook, MatchError> {
+        let text = std::fs::read_to_string(rule_file)
+            .map_err(|e| MatchError::FileError(format!("{} : {}", rule_file, e)))?;
+
+        if let Some(error) = lint::check(&text, None).into_iter().find(|diagnostic| diagnostic.is_error()) {
+            return Err(MatchError::InvalidRule(format!("{} {}", rule_file, error)));
+        }
+
+        let json:serde_json::Value = serde_json::from_str(&text)?;
+        Ok(RuleBook::from_json(json)?)
+    }
+
+    /// Values of the person and the rules are compared by their canonical value
+    pub fn withRules(person:DocPerson, rules:&RuleBook, lookups:&Lookups) -> Result<Option<Self>, MatchError> {
+        let mut person = person;
+        lookups.dictionary.normalize_person(&mut person);
+
+        let (matchers, aggregation, response) = match rules {
+            RuleBook::QdRules(policies) => Self::matchersFromQdRules(&person, policies, &lookups.dictionary)?,
+            RuleBook::Rules(rules) => Self::matchersFromRules(&person, rules, lookups)?
+        };
+
+        if matchers.is_empty() {
+            return Err(MatchError::NoRulesFound);
+        }
+
+        Ok(Some(Match {
+            person,
+            matchers,
+            aggregation,
+            excluded:HashSet::new(),
+            prior_matches:HashMap::new(),
+            seed:None,
+            response,
+            min_score:0.0,
+            offset:0,
+            limit:None
+        }))
+    }
+
+    /// Matchers of the policy serving the person, with its aggregation and response boost
+    fn matchersFromRules(person:&DocPerson, json:&MatchRule, lookups:&Lookups) -> Result<(Vec<FieldMatcher>, Aggregation, Option<ResponseBoost>), MatchError> {
+        let mut matchers:Vec<FieldMatcher> = Vec::new();
+
+        //Load only one policy at a time
+        let dictionary = &lookups.dictionary;
+        let policy = match json.policy.iter().find(|policy| serves(person, &policy.p_self, &policy.p_partner, dictionary)) {
+            Some(policy) => policy,
+            None => return Err(no_policy(person))
+        };
+
+        for rule_id in &policy.p_rules {
+            //Policy refers to a rule which doesn't exist
+            let rule = match json.rule(*rule_id) {
+                Some(rule) => rule,
+                None => continue
+            };
+
+            //Required or not, there is nothing to compare with
+            if lacks_own_value(rule, person) {
                 continue;
             }
 
-            for rule_id in policy.p_rules {
-                for rule in &json.rules {
-                    //Not the rule we are looking for
-                    if rule.rule_id != rule_id {
-                        continue;
+            match FieldMatcher::new(rule, json, lookups, person) {
+                Some(matcher) => {
+                    matchers.push(matcher);
+                }
+
+                //No row for this person in the rule, it doesn't apply
+                None => {
+                    if rule.rule_required {
+                        return Err(MatchError::NoRulesFound);
                     }
+                }
+            }
+        }
 
-                    //We got the rule
-                    match rule_id {
-
-                        //Age Rule
-                        1 => {
-                            // Self deines the gender in Age Rule
-                            let mut self_age = person.age.parse::<f32>().unwrap_or_default();
-                            for node in &rule.rule_data {
-                                if node.r_self.to_lowercase() == person.gender.to_lowercase() {
-                                    let age_priority_list = (&node.r_seek_float).into_iter().map(|x| x + self_age).collect();
-                                    age = MatcherFilter::new(&age_priority_list);
-                                    break;
-                                }
-                            }
-                        }
+        Self::addSeekingMatcher(&mut matchers, &policy.p_partner, dictionary);
+        Ok((matchers, policy.p_aggregation, json.response_rating))
+    }
 
-                        //Gender Rule
-                        2 => {
-                            //Sef define gender in Gender rule
-                            for node in &rule.rule_data {
-                                if node.r_self.to_lowercase() == person.gender.to_lowercase() {
-                                    gender = MatcherFilter::new(&node.r_seek_string);
-                                    break;
-                                }
-                            }
+    fn matchersFromQdRules(person:&DocPerson, policies:&Vec<QdPolicy>, dictionary:&Dictionary) -> Result<(Vec<FieldMatcher>, Aggregation, Option<ResponseBoost>), MatchError> {
+        let mut matchers:Vec<FieldMatcher> = Vec::new();
 
-                        }
+        //Load only one policy at a time
+        let policy = match policies.iter().find(|policy| serves(person, &policy.first, &policy.second, dictionary)) {
+            Some(policy) => policy,
+            None => return Err(no_policy(person))
+        };
 
-                        //Education
-                        3 | 4 => {
-                            for node in &rule.rule_data {
-                                if node.r_self.to_lowercase() == person.education.to_lowercase() {
-                                    education = MatcherFilter::new(&node.r_seek_string);
-                                    break;
-                                }
-                            }
-
-                            match education {
-                                None => {
-                                    education = MatcherFilter::new(&(Vec::new()));
-                                }
-
-                                _=> {}
-                            }
-                        }
+        //Matchers are evaluated in the order of role_priority
+        for (field, role) in policy.ordered_roles() {
+            if let Some(matcher) = FieldMatcher::from_role(field, role, dictionary, person)? {
+                matchers.push(matcher);
+            }
+        }
 
-                        //Verbal
-                        5 => {
-                            for node in &rule.rule_data {
-                                if node.r_self.to_lowercase() == person.verbal_ability.to_lowercase() {
-                                    verbal = MatcherFilter::new(&node.r_seek_string);
-                                    break;
-                                }
-                            }
-
-                            //Add Default list with zero element
-                            match verbal {
-                                None => {
-                                    verbal = MatcherFilter::new(&(Vec::new()));
-                                }
-
-                                _=> {}
-                            }
-                        }
+        Self::addSeekingMatcher(&mut matchers, &policy.second, dictionary);
+        Ok((matchers, policy.aggregation, policy.response_boost))
+    }
+
+    /// Policies without a gender rule only get the gender the policy is for
+    fn addSeekingMatcher(matchers:&mut Vec<FieldMatcher>, partner:&str, dictionary:&Dictionary) {
+        if matchers.iter().any(|matcher| matcher.field == "gender") {
+            return;
+        }
+
+        if let Some(matcher) = FieldMatcher::seeking(partner, dictionary) {
+            matchers.insert(0, matcher);
+        }
+    }
+
+    //Return the Query Which picks the sorted collection from database
+    pub fn getFilter(&self) -> Filter {
+
+        //Never match the person with themselves
+        let mut conditions:Vec<Filter> = vec![Filter::NotIn(String::from("qid"), vec![self.person.qid.clone()])];
+
+        //Every cut rule is applied by the database
+        for field_matcher in &self.matchers {
+            if let Some(condition) = field_matcher.filter() {
+                conditions.push(condition);
+            }
+        }
+
+        Filter::And(conditions)
+    }
+
+    //Calculate for cadidate person. Rules without weight don't count, rules the candidate
+    //has no value for are left out unless they are required, then they score zero
+    fn calculateScore(&self, candidate:&CandidatePerson) -> f32 {
+        let mut total_weight = 0.0;
+        let mut score = 0.0;
+
+        for matcher in self.matchers.iter().filter(|matcher| matcher.weight > 0.0) {
+            let value = match matcher.calculate(candidate) {
+                Some(value) => value,
+                None if matcher.required => 0.0,
+                None => continue
+            };
 
-                        _ => {}
+            total_weight += matcher.weight;
+            match self.aggregation {
+                Aggregation::GeometricMean => {
+                    if value <= 0.0 {
+                        return 0.0;
                     }
+                    score += matcher.weight * value.ln();
+                }
+
+                Aggregation::WeightedMean | Aggregation::Lexicographic => {
+                    score += matcher.weight * value;
                 }
             }
+        }
 
-            //Load only one policy at a time
-            break;
+        if total_weight <= 0.0 {
+            return 0.0;
         }
 
-        match (age, gender, education, verbal) {
-            (Some(age), Some(gender), Some(education), Some(verbal)) => {
-                return Ok(Some(Match {
-                    person,
-                    age,
-                    gender,
-                    education,
-                    verbal
-                }));
+        let score = match self.aggregation {
+            Aggregation::GeometricMean => (score / total_weight).exp(),
+            Aggregation::WeightedMean | Aggregation::Lexicographic => score / total_weight
+        };
+
+        match (self.response, candidate.response_rating) {
+            (Some(response), Some(rating)) => (score + response.adjustment(rating)).max(0.0).min(100.0),
+            _ => score
+        }
+    }
+
+    /// Score of every rule in priority order, rules which don't apply score zero
+    fn ruleScores(&self, candidate:&CandidatePerson) -> Vec<f32> {
+        self.matchers.iter()
+            .map(|matcher| matcher.calculate(candidate).unwrap_or_default())
+            .collect()
+    }
+
+    /// Record the contribution of every rule to the score of the candidates
+    pub fn explain(&self, candidates:&mut Vec<CandidatePerson>) {
+        for candidate in candidates.iter_mut() {
+            candidate.explanation = self.matchers.iter()
+                .map(|matcher| matcher.explain(candidate))
+                .collect();
+        }
+    }
+
+    /// Like `explain`, with how every candidate scored the person by their own policy as well
+    pub fn explainMutual(&self, candidates:&mut Vec<CandidatePerson>, rules:&RuleBook, lookups:&Lookups) {
+        self.explain(candidates);
+
+        let person = CandidatePerson::from(&self.person);
+        for candidate in candidates.iter_mut() {
+            if let Ok(Some(their_match)) = Self::withRules(candidate.to_person(), rules, lookups) {
+                candidate.their_explanation = their_match.matchers.iter()
+                    .map(|matcher| matcher.explain(&person))
+                    .collect();
             }
-            _ => {}
         }
-        
-        return Err(MatchError::NoRulesFound);
+    }
 
-        
+    /// Never suggest these qids, like persons already met or blocked
+    pub fn exclude(&mut self, qids:HashSet<String>) {
+        self.excluded.extend(qids);
     }
 
-    //Return the Query Which picks the sorted collection from database
-    pub fn getFilter(&self) -> impl Into<Option<bson::ordered::OrderedDocument>> {   
-        
-        //Put the age and Gender filter as of now
-        Some(doc! {"gender":{"$in":self.gender.getList()}})
+    /// Number of persons each candidate already met, fewer ranks first on equal score
+    pub fn priorMatches(&mut self, counts:HashMap<String, usize>) {
+        self.prior_matches = counts;
     }
 
-    //Calculate for cadidate person
-    fn calculateScore(&self, candidate:&CandidatePerson) -> f32 {
-        let mut weight = 0.0;
-        weight += self.age.calculate(&candidate.age);
-        weight += self.gender.calculate(&candidate.gender);
-        weight += self.verbal.calculate(&candidate.verbal);
-        weight += self.education.calculate(&candidate.education);
-        weight/4.0
+    /// Break ties in a random order which is the same for the same seed
+    pub fn seed(&mut self, seed:Option<u64>) {
+        self.seed = seed;
+    }
+
+    /// Leave candidates scoring less than `score` out of the results
+    pub fn minScore(&mut self, score:f32) {
+        self.min_score = score;
+    }
+
+    /// Results start after `offset` ranked candidates and hold at most `limit` of them
+    pub fn page(&mut self, offset:usize, limit:Option<usize>) {
+        self.offset = offset;
+        self.limit = limit;
     }
 
-    //Function which take can candidate like and return sorted list
+    /// False if the candidate is excluded, is the person or any cut rule of this person drops the candidate
+    fn accepts(&self, candidate:&CandidatePerson) -> bool {
+        if candidate.qid == self.person.qid || self.excluded.contains(&candidate.qid) {
+            return false;
+        }
+
+        self.matchers.iter().all(|matcher| matcher.accepts(candidate))
+    }
+
+    //Function which take can candidate like and return sorted list.
+    //Equal scores are ranked by response rating when the rules use it, then by fewest prior matches,
+    //then by qid, or randomly by seed when one is given. Only the page set by `minScore` and `page` is returned.
     pub fn qurate(&mut self, candidates:Vec<CandidatePerson>) -> Vec<CandidatePerson> {
         
         let mut sortList:Vec<CandidatePerson> = Vec::new();
 
-        //Filter
-
-
-        //Calculate Score
         for c in candidates {
             let mut candidate = c;
+
+            //Filter, cut matchers are checked in priority order
+            if !self.accepts(&candidate) {
+                continue;
+            }
+
+            //Calculate Score
             candidate.match_score = self.calculateScore(&candidate);
             sortList.push(candidate);
             
         }
-        sortList.shuffle(&mut thread_rng());
+
+        self.select(self.rank(sortList))
+    }
+
+    /// Like `qurate`, but every candidate scores the person by their own policy as well.
+    /// Candidates whose policy drops the person are filtered out, the score is the
+    /// harmonic mean of both directions.
+    pub fn qurateMutual(&mut self, candidates:Vec<CandidatePerson>, rules:&RuleBook, lookups:&Lookups) -> Vec<CandidatePerson> {
+
+        let mut sortList:Vec<CandidatePerson> = Vec::new();
+        let person = CandidatePerson::from(&self.person);
+
+        for c in candidates {
+            let mut candidate = c;
+            if !self.accepts(&candidate) {
+                continue;
+            }
+
+            //Candidate without a policy can't be interested in anyone
+            let their_match = match Self::withRules(candidate.to_person(), rules, lookups) {
+                Ok(Some(their_match)) => their_match,
+                _ => continue
+            };
+
+            if !their_match.accepts(&person) {
+                continue;
+            }
+
+            let score = self.calculateScore(&candidate);
+            let their_score = their_match.calculateScore(&person);
+            candidate.match_score = harmonic_mean(score, their_score);
+            sortList.push(candidate);
+        }
+
+        self.select(self.rank(sortList))
+    }
+
+    /// Ranked candidates above the minimum score, on the requested page
+    fn select(&self, ranked:Vec<CandidatePerson>) -> Vec<CandidatePerson> {
+        let selected = ranked.into_iter()
+            .filter(|candidate| candidate.match_score >= self.min_score)
+            .skip(self.offset);
+
+        match self.limit {
+            Some(limit) => selected.take(limit).collect(),
+            None => selected.collect()
+        }
+    }
+
+    fn rank(&self, candidates:Vec<CandidatePerson>) -> Vec<CandidatePerson> {
+        let mut sortList = candidates;
+
+        //Start from a known order, the database may return candidates in any order.
+        //Sorts below are stable, so the order breaks the tie of the next sort
+        sortList.sort_by(|a, b| a.qid.cmp(&b.qid));
+        match self.seed {
+            Some(seed) => {
+                sortList.shuffle(&mut StdRng::seed_from_u64(seed));
+            }
+
+            None => {
+                sortList.sort_by_key(|candidate| *self.prior_matches.get(&candidate.qid).unwrap_or(&0));
+            }
+        }
+
+        //Boost can't lift a full score any higher, better rated candidates go first on a tie
+        if let Some(response) = self.response {
+            let rating = |candidate:&CandidatePerson| candidate.response_rating.unwrap_or(response.neutral);
+            sortList.sort_by(|a, b| rating(b).partial_cmp(&rating(a)).unwrap_or(std::cmp::Ordering::Equal));
+        }
         sortList.sort_by(|a, b| b.cmp_score(a));
+
+        //Rule by rule in priority order, match score breaks the tie
+        if self.aggregation == Aggregation::Lexicographic {
+            let mut keyed:Vec<(Vec<f32>, CandidatePerson)> = sortList.into_iter()
+                .map(|candidate| (self.ruleScores(&candidate), candidate))
+                .collect();
+            keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+            sortList = keyed.into_iter().map(|(_, candidate)| candidate).collect();
+        }
+
         sortList
     }
 }
 
+
+pub(crate) fn harmonic_mean(a:f32, b:f32) -> f32 {
+    if a + b <= 0.0 {
+        return 0.0;
+    }
+
+    2.0 * a * b / (a + b)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::qdmatch::testing::{person, ranking};
+
+    fn candidate(qid:&str, age:Option<f32>, education:&str, verbal:&str) -> CandidatePerson {
+        let mut candidate = person(qid, qid, "Male");
+        candidate.age = age;
+        candidate.education = String::from(education);
+        candidate.verbal = String::from(verbal);
+        candidate
+    }
+
+    fn seeker() -> DocPerson {
+        let mut seeker = person("Q-0", "seeker", "Female").to_person();
+        seeker.age = String::from("30");
+        seeker
+    }
+
+    /// Required age band, a verbal rule without weight and an education rule
+    fn rules(aggregation:&str) -> RuleBook {
+        RuleBook::from_json(json!({
+            "rules":[
+                {"rule_name":"age", "rule_id":1, "rule_field":"age", "rule_kind":"offset", "rule_weight":2, "rule_required":true,
+                    "rule_data":[{"r_self":"*", "r_tolerance":{"band":[-2, 2], "falloff":2}}]},
+                {"rule_name":"verbal", "rule_id":2, "rule_field":"verbal_ability", "rule_weight":0,
+                    "rule_data":[{"r_self":"*", "r_seek_string":["Good"]}]},
+                {"rule_name":"education", "rule_id":3, "rule_field":"education",
+                    "rule_data":[{"r_self":"*", "r_seek_string":["Graduation"]}]}
+            ],
+            "policy":[{"p_self":"Female", "p_partner":"Male", "p_rules":[1, 2, 3], "p_aggregation":aggregation}]
+        })).unwrap()
+    }
+
+    #[test]
+    fn rules_without_weight_do_not_count() {
+        let matcher = Match::withRules(seeker(), &rules("geometric_mean"), &Lookups::bundled()).ok().unwrap().unwrap();
+        let score = matcher.calculateScore(&candidate("Q-1", Some(31.0), "Graduation", "Poor"));
+        assert!((score - 100.0).abs() < 0.01, "scored {}", score);
+    }
+
+    #[test]
+    fn missing_required_value_scores_zero() {
+        let matcher = Match::withRules(seeker(), &rules("weighted_mean"), &Lookups::bundled()).ok().unwrap().unwrap();
+        let in_band = matcher.calculateScore(&candidate("Q-1", Some(31.0), "Graduation", "Good"));
+        let without_age = matcher.calculateScore(&candidate("Q-2", None, "Graduation", "Good"));
+        assert!((in_band - 100.0).abs() < 0.01, "scored {}", in_band);
+        assert!((without_age - 100.0 / 3.0).abs() < 0.01, "scored {}", without_age);
+
+        let matcher = Match::withRules(seeker(), &rules("geometric_mean"), &Lookups::bundled()).ok().unwrap().unwrap();
+        assert_eq!(matcher.calculateScore(&candidate("Q-2", None, "Graduation", "Good")), 0.0);
+    }
+
+    #[test]
+    fn ranges_explain_their_offset() {
+        let matcher = Match::withRules(seeker(), &rules("weighted_mean"), &Lookups::bundled()).ok().unwrap().unwrap();
+        let mut candidates = vec![
+            candidate("Q-1", Some(29.0), "Graduation", "Good"),
+            candidate("Q-2", Some(33.5), "Graduation", "Good")
+        ];
+        matcher.explain(&mut candidates);
+
+        let age:Vec<&ScoreDetail> = candidates.iter()
+            .map(|candidate| candidate.explanation.iter().find(|detail| detail.rule == "age").unwrap())
+            .collect();
+        assert_eq!(age[0].band, Some((28.0, 32.0)));
+        assert_eq!(age[0].offset, Some(0.0));
+        assert_eq!(age[0].position, None);
+        assert_eq!(age[1].offset, Some(1.5));
+        assert!(age[0].to_string().contains("in band"));
+        assert!(age[1].to_string().contains("1.5 above"));
+        assert!(age[1].to_string().contains("[28, 32]"));
+    }
+
+    #[test]
+    fn age_rules_skip_seekers_without_age() {
+        let mut seeker = seeker();
+        seeker.age = String::new();
+
+        let matcher = Match::withRules(seeker.clone(), &rules("weighted_mean"), &Lookups::bundled()).ok().unwrap().unwrap();
+        assert!(matcher.matchers.iter().all(|matcher| matcher.field != "age"));
+        assert_eq!(matcher.calculateScore(&candidate("Q-1", Some(31.0), "Graduation", "Good")), 100.0);
+
+        seeker.city = String::from("Pune");
+        seeker.education = String::from("Graduation");
+        let rules = Match::loadRules(&String::from("qd_rules.json")).ok().unwrap();
+        let matcher = Match::withRules(seeker, &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+        let filter = format!("{:?}", matcher.getFilter());
+        assert!(!filter.contains("Between"), "{}", filter);
+    }
+
+    #[test]
+    fn seekers_of_anyone_have_a_policy() {
+        let rules = Match::loadRules(&String::from("qd_rules.json")).ok().unwrap();
+        let mut seeker = seeker();
+        seeker.city = String::from("Pune");
+        seeker.education = String::from("Graduation");
+        seeker.seeking = String::from("Both");
+
+        let matcher = Match::withRules(seeker.clone(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+        assert!(matcher.matchers.iter().all(|matcher| matcher.field != "gender"));
+
+        seeker.gender = String::from("Other");
+        match Match::withRules(seeker, &rules, &Lookups::bundled()) {
+            Err(e @ MatchError::NoPolicy(..)) => assert_eq!(e.to_string(), "no policy for Other seeking Any"),
+            _ => panic!("expected no policy")
+        }
+    }
+
+    fn qids(candidates:&[CandidatePerson]) -> Vec<&str> {
+        candidates.iter().map(|candidate| &candidate.qid[..]).collect()
+    }
+
+    /// Candidates scoring alike, in the order the database returned them
+    fn ties() -> Vec<CandidatePerson> {
+        ["Q-4", "Q-2", "Q-5", "Q-1", "Q-3"].iter()
+            .map(|qid| candidate(qid, Some(30.0), "Graduation", "Good"))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_order() {
+        let rules = rules("weighted_mean");
+        let ranked = |candidates:Vec<CandidatePerson>, seed:u64| {
+            let mut matcher = Match::withRules(seeker(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+            matcher.seed(Some(seed));
+            matcher.qurate(candidates)
+        };
+
+        let mut reversed = ties();
+        reversed.reverse();
+        let first = ranked(ties(), 7);
+        assert_eq!(qids(&first), qids(&ranked(reversed, 7)));
+        assert_eq!(first.len(), 5);
+    }
+
+    #[test]
+    fn ties_break_by_prior_matches_then_qid() {
+        let mut matcher = Match::withRules(seeker(), &rules("weighted_mean"), &Lookups::bundled()).ok().unwrap().unwrap();
+        let mut met = HashMap::new();
+        met.insert(String::from("Q-1"), 2);
+        met.insert(String::from("Q-4"), 1);
+        matcher.priorMatches(met);
+
+        let mut reversed = ties();
+        reversed.reverse();
+        assert_eq!(qids(&matcher.qurate(ties())), vec!["Q-2", "Q-3", "Q-5", "Q-4", "Q-1"]);
+        assert_eq!(qids(&matcher.qurate(reversed)), vec!["Q-2", "Q-3", "Q-5", "Q-4", "Q-1"]);
+    }
+
+    #[test]
+    fn results_are_paged_after_the_minimum_score() {
+        let mut matcher = Match::withRules(seeker(), &rules("weighted_mean"), &Lookups::bundled()).ok().unwrap().unwrap();
+        let scored = || vec![
+            candidate("Q-3", Some(35.0), "Graduation", ""),
+            candidate("Q-1", Some(30.0), "Graduation", ""),
+            candidate("Q-2", Some(30.0), "High School", "")
+        ];
+        assert_eq!(qids(&matcher.qurate(scored())), vec!["Q-1", "Q-2", "Q-3"]);
+
+        //Candidates scoring the minimum exactly are kept
+        matcher.minScore(100.0);
+        assert_eq!(qids(&matcher.qurate(scored())), vec!["Q-1"]);
+        matcher.minScore(100.01);
+        assert!(matcher.qurate(scored()).is_empty());
+
+        matcher.minScore(0.0);
+        matcher.page(1, Some(1));
+        assert_eq!(qids(&matcher.qurate(scored())), vec!["Q-2"]);
+        matcher.page(0, Some(0));
+        assert!(matcher.qurate(scored()).is_empty());
+        matcher.page(3, None);
+        assert!(matcher.qurate(scored()).is_empty());
+        matcher.page(2, Some(10));
+        assert_eq!(qids(&matcher.qurate(scored())), vec!["Q-3"]);
+
+        //Offset counts the candidates above the minimum only
+        matcher.minScore(50.0);
+        matcher.page(1, None);
+        assert_eq!(qids(&matcher.qurate(scored())), vec!["Q-2"]);
+    }
+
+    #[test]
+    fn qd_policies_boost_by_response_rating() {
+        let rules = RuleBook::from_json(json!([{
+            "first":"female",
+            "second":"male",
+            "response_boost":{"neutral":7, "boost":5, "penalty":15},
+            "education":{"type":"weighted", "list_type":"1d", "list":["Graduation", "High School"]}
+        }])).unwrap();
+        let matcher = Match::withRules(seeker(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+
+        let mut rated = candidate("Q-1", None, "High School", "");
+        rated.response_rating = Some(10.0);
+        let unrated = candidate("Q-2", None, "High School", "");
+        assert!((matcher.calculateScore(&rated) - matcher.calculateScore(&unrated) - 5.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn synonyms_are_queried_like_they_are_matched() {
+        let rules = RuleBook::from_json(json!([{
+            "first":"female",
+            "second":"male",
+            "education":{"type":"cut", "list_type":"1d", "list":["Graduation"]}
+        }])).unwrap();
+        let matcher = Match::withRules(seeker(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+        let filter = matcher.getFilter();
+
+        for education in &["B.Tech ", "b.tech", "BTech", "graduation"] {
+            let stored = candidate("Q-1", None, education, "");
+            assert!(matcher.accepts(&stored), "{}", education);
+            assert!(filter.accepts(&stored.to_person()), "{}", education);
+        }
+        assert!(!filter.accepts(&candidate("Q-1", None, "M.Tech", "").to_person()));
+        assert_eq!(Pattern::Key(String::from("B.Tech")).regex(), "^[\\W_]*b[\\W_]*t[\\W_]*e[\\W_]*c[\\W_]*h[\\W_]*$");
+    }
+
+    fn names(matcher:&Match) -> Vec<&str> {
+        matcher.matchers.iter().map(|matcher| &matcher.name[..]).collect()
+    }
+
+    #[test]
+    fn roles_follow_role_priority() {
+        let rules = RuleBook::from_json(json!([{
+            "first":"female",
+            "second":"male",
+            "aggregation":"lexicographic",
+            "role_priority":["verbal_ability", "education"],
+            "city":{"type":"weighted", "list_type":"1d", "list":["Pune"]},
+            "education":{"type":"weighted", "list_type":"1d", "list":["Graduation", "High School"]},
+            "verbal_ability":{"type":"weighted", "list_type":"1d", "list":["Good", "Average"]}
+        }])).unwrap();
+        let mut matcher = Match::withRules(seeker(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+        assert_eq!(names(&matcher), vec!["seeking", "verbal_ability", "education", "city"]);
+
+        //First role in priority decides, whatever the other roles score
+        let mut fluent = candidate("Q-1", None, "High School", "Good");
+        fluent.city = String::from("Mumbai");
+        let mut graduate = candidate("Q-2", None, "Graduation", "Average");
+        graduate.city = String::from("Pune");
+        assert_eq!(qids(&matcher.qurate(vec![graduate, fluent])), vec!["Q-1", "Q-2"]);
+    }
+
+    #[test]
+    fn two_d_roles_pick_the_row_of_the_person() {
+        let rules = RuleBook::from_json(json!([{
+            "first":"female",
+            "second":"male",
+            "education":{"type":"cut", "list_type":"2d", "list":[
+                {"self":"Graduation", "partner":["Post Graduation", "Graduation"]},
+                {"self":"High School", "partner":["High School"]}
+            ]},
+            "verbal_ability":{"type":"weighted", "list_type":"1d", "list":["Good"]}
+        }])).unwrap();
+        let mut seeker = seeker();
+        seeker.education = String::from("B.Tech");
+        let matcher = Match::withRules(seeker.clone(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+
+        let education = matcher.matchers.iter().find(|matcher| matcher.name == "education").unwrap();
+        assert_eq!(education.calculate(&candidate("Q-1", None, "MBA", "")), Some(100.0));
+        assert_eq!(education.calculate(&candidate("Q-2", None, "Graduation", "")), Some(50.0));
+        assert!(!matcher.accepts(&candidate("Q-3", None, "High School", "")));
+
+        //No row for the person, the role doesn't apply to them
+        seeker.education = String::from("P.H.D");
+        let matcher = Match::withRules(seeker, &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+        assert_eq!(names(&matcher), vec!["seeking", "verbal_ability"]);
+        assert!(matcher.accepts(&candidate("Q-3", None, "High School", "")));
+    }
+
+    #[test]
+    fn cut_rules_are_compiled_into_the_filter() {
+        let rules = RuleBook::from_json(json!({
+            "rules":[
+                {"rule_name":"age", "rule_id":1, "rule_field":"age", "rule_kind":"offset", "rule_cut":true,
+                    "rule_data":[{"r_self":"*", "r_seek_float":[1, -1, 0]}]},
+                {"rule_name":"education", "rule_id":2, "rule_field":"education", "rule_cut":true,
+                    "rule_data":[{"r_self":"*", "r_seek_string":["Graduation"]}]},
+                {"rule_name":"verbal", "rule_id":3, "rule_field":"verbal_ability",
+                    "rule_data":[{"r_self":"*", "r_seek_string":["Good"]}]}
+            ],
+            "policy":[{"p_self":"Female", "p_partner":"Male", "p_rules":[1, 2, 3]}]
+        })).unwrap();
+        let matcher = Match::withRules(seeker(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+
+        let conditions = match matcher.getFilter() {
+            Filter::And(conditions) => conditions,
+            filter => panic!("unexpected filter {:?}", filter)
+        };
+        assert_eq!(conditions.len(), 4, "{:?}", conditions);
+        assert_eq!(conditions[0], Filter::NotIn(String::from("qid"), vec![String::from("Q-0")]));
+        assert!(match &conditions[1] { Filter::Matches(field, _) => field == "gender", _ => false });
+        assert_eq!(conditions[2], Filter::Between(String::from("age"), 29.0, 31.0));
+        match &conditions[3] {
+            Filter::Matches(field, patterns) => {
+                assert_eq!(field, "education");
+                assert_eq!(patterns[0], Pattern::Is(String::from("graduation")));
+            }
+            filter => panic!("unexpected filter {:?}", filter)
+        }
+
+        //Database keeps the candidates the matcher accepts
+        let filter = matcher.getFilter();
+        for (age, education) in &[(30.0, "Graduation"), (31.0, "B.Sc"), (32.0, "Graduation"), (30.0, "MBA")] {
+            let stored = candidate("Q-1", Some(*age), education, "");
+            assert_eq!(filter.accepts(&stored.to_person()), matcher.accepts(&stored), "{} {}", age, education);
+        }
+    }
+
+    #[test]
+    fn mutual_matches_drop_candidates_who_reject_the_person() {
+        let rules = ranking(&[("Q-0", &["x", "y", "z"]), ("Q-1", &["seeker"]), ("Q-2", &["someone"])]);
+        let candidates = || vec![
+            person("Q-1", "x", "Male"), person("Q-2", "y", "Male"),
+            person("Q-3", "z", "Male"), person("Q-4", "w", "Nonbinary")
+        ];
+        let mut matcher = Match::withRules(seeker(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+        assert_eq!(qids(&matcher.qurate(candidates())), vec!["Q-1", "Q-2", "Q-3"]);
+
+        //y seeks someone else, w has no policy, z has no row and accepts anyone
+        let mutual = matcher.qurateMutual(candidates(), &rules, &Lookups::bundled());
+        assert_eq!(qids(&mutual), vec!["Q-1", "Q-3"]);
+        assert_eq!(mutual[0].match_score, 100.0);
+    }
+
+    /// Policy of a single cut rule
+    fn cut_rule(rule:serde_json::Value, categories:serde_json::Value) -> RuleBook {
+        let mut rule = rule;
+        rule["rule_id"] = json!(1);
+        rule["rule_name"] = rule["rule_field"].clone();
+        rule["rule_cut"] = json!(true);
+        RuleBook::from_json(json!({
+            "rules":[rule],
+            "policy":[{"p_self":"Female", "p_partner":"Male", "p_rules":[1]}],
+            "categories":categories
+        })).unwrap()
+    }
+
+    /// Conditions of the filter other than leaving out the person and the gender of the policy
+    fn conditions(matcher:&Match) -> Vec<Filter> {
+        match matcher.getFilter() {
+            Filter::And(conditions) => conditions.into_iter().skip(2).collect(),
+            filter => panic!("unexpected filter {:?}", filter)
+        }
+    }
+
+    #[test]
+    fn overlap_scores_the_best_shared_value() {
+        let rules = cut_rule(json!({"rule_field":"languages", "rule_kind":"overlap"}), json!({}));
+        let mut seeker = seeker();
+        seeker.languages = vec![String::from("Mar"), String::from("Hindi"), String::from("marathi")];
+        let matcher = Match::withRules(seeker, &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+
+        let speaking = |languages:&[&str]| {
+            let mut candidate = candidate("Q-1", None, "", "");
+            candidate.languages = languages.iter().map(|language| language.to_string()).collect();
+            candidate
+        };
+        assert_eq!(matcher.calculateScore(&speaking(&["Marathi"])), 100.0);
+        assert_eq!(matcher.calculateScore(&speaking(&["Eng", "Hin"])), 50.0);
+        assert!(!matcher.accepts(&speaking(&["English"])));
+        assert!(!matcher.accepts(&speaking(&[])));
+
+        let key = |value:&str| Pattern::Key(String::from(value));
+        assert_eq!(conditions(&matcher), vec![Filter::Matches(String::from("languages"), vec![
+            Pattern::Is(String::from("marathi")), Pattern::Is(String::from("hindi")),
+            key("Hindi"), key("Hin"), key("Marathi"), key("Mar")
+        ])]);
+    }
+
+    #[test]
+    fn matrix_scores_by_category() {
+        let rules = cut_rule(json!({"rule_field":"profession", "rule_kind":"matrix", "rule_data":[
+            {"r_self":"Engineering", "r_seek_string":["Engineering", "Medicine"], "r_seek_float":[100, 60]},
+            {"r_self":"*", "r_seek_string":["Medicine"], "r_seek_float":[100]}
+        ]}), json!({"profession":[
+            {"c_name":"Engineering", "c_keywords":["engineer", "developer"]},
+            {"c_name":"Medicine", "c_keywords":["doctor"]}
+        ]}));
+
+        //Seeker's row is picked by the category of their profession
+        let mut seeker = seeker();
+        seeker.profession = String::from("Civil Engineer");
+        let matcher = Match::withRules(seeker, &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+
+        let working = |profession:&str| {
+            let mut candidate = candidate("Q-1", None, "", "");
+            candidate.profession = String::from(profession);
+            candidate
+        };
+        assert_eq!(matcher.calculateScore(&working("Software Developer")), 100.0);
+        assert_eq!(matcher.calculateScore(&working("Doctor")), 60.0);
+        assert!(matcher.accepts(&working("medicine")));
+        assert!(!matcher.accepts(&working("Lawyer")));
+
+        let filter = conditions(&matcher);
+        assert_eq!(filter, vec![Filter::Matches(String::from("profession"), vec![
+            Pattern::Is(String::from("engineering")), Pattern::Is(String::from("medicine")),
+            Pattern::Word(String::from("engineer")), Pattern::Word(String::from("developer")), Pattern::Word(String::from("doctor"))
+        ])]);
+        assert!(filter[0].accepts(&working("Software Developer").to_person()));
+        assert!(!filter[0].accepts(&working("Lawyer").to_person()));
+    }
+
+    #[test]
+    fn distance_scores_by_km() {
+        let rules = cut_rule(json!({"rule_field":"city", "rule_kind":"distance", "rule_data":[
+            {"r_self":"*", "r_tolerance":{"band":[0, 5], "falloff":6}}
+        ]}), json!({}));
+        let mut seeker = seeker();
+        seeker.city = String::from("Poona");
+        let matcher = Match::withRules(seeker.clone(), &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+
+        let living = |city:&str| {
+            let mut candidate = candidate("Q-1", None, "", "");
+            candidate.city = String::from(city);
+            candidate
+        };
+        assert_eq!(matcher.calculateScore(&living("pune")), 100.0);
+        let hadapsar = matcher.calculateScore(&living("Hadapsar"));
+        assert!(hadapsar > 55.0 && hadapsar < 65.0, "scored {}", hadapsar);
+        assert!(matcher.accepts(&living("Kharadi")));
+        assert!(!matcher.accepts(&living("Wakad")));
+        assert!(!matcher.accepts(&living("Mumbai")));
+        assert!(!matcher.accepts(&living("Atlantis")));
+
+        //Cities within the band and its falloff, by every name they go by
+        let names = |names:&[&str]| names.iter().map(|name| Pattern::Is(name.to_string())).collect::<Vec<Pattern>>();
+        assert_eq!(conditions(&matcher), vec![Filter::Matches(String::from("city"), names(&["Pune", "Poona", "Kharadi", "Hadapsar"]))]);
+
+        //Nothing to measure from for a city which is not in the table
+        seeker.city = String::from("Atlantis");
+        let matcher = Match::withRules(seeker, &rules, &Lookups::bundled()).ok().unwrap().unwrap();
+        assert!(matcher.matchers.iter().all(|matcher| matcher.field != "city"));
+        assert!(matcher.accepts(&living("Mumbai")));
+    }
+}
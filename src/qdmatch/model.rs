@@ -1,20 +1,116 @@
 use std::fmt;
 use std::cmp::Ordering;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use crate::db::db_models::DocPerson;
 
 
-#[derive(Debug, Deserialize)]
+/// Contribution of one rule to the match score of a candidate
+#[derive(Debug, Serialize, Clone)]
+pub struct ScoreDetail {
+    pub rule:String,
+    pub field:String,
+
+    /// Preference list of the person looking for a date, best first
+    pub seeks:Vec<String>,
+
+    /// Value of the candidate for the field
+    pub value:String,
+
+    /// Position of the value in `seeks`, None if it is not in the list or `seeks` is a band
+    pub position:Option<usize>,
+
+    /// Lowest and highest value scoring full, for range and distance rules
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub band:Option<(f32, f32)>,
+
+    /// How far the value is outside of `band`, negative below it and zero inside it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset:Option<f32>,
+
+    /// Unit of `band` and `offset`, km for distance rules
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub unit:String,
+
+    /// Score of the rule, None if the rule doesn't apply to the candidate
+    pub score:Option<f32>,
+    pub weight:f32,
+}
+
+impl fmt::Display for ScoreDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = if self.unit.is_empty() { String::new() } else { format!(" {}", self.unit) };
+
+        //Bands have no position, only the offset of the value
+        let (position, seeks) = match self.band {
+            Some((min, max)) => {
+                let offset = match self.offset {
+                    Some(offset) if offset < 0.0 => format!("{}{} below", rounded(-offset), unit),
+                    Some(offset) if offset > 0.0 => format!("{}{} above", rounded(offset), unit),
+                    Some(_) => String::from("in band"),
+                    None => String::from("-")
+                };
+                (offset, format!("{}, {}{}", rounded(min), rounded(max), unit))
+            }
+
+            None => {
+                let position = match self.position {
+                    Some(position) => format!("#{} of {}", position + 1, self.seeks.len()),
+                    None => format!("- of {}", self.seeks.len())
+                };
+                (position, self.seeks.join(", "))
+            }
+        };
+        let score = match self.score {
+            Some(score) => format!("{}%", score as u8),
+            None => String::from("n/a")
+        };
+
+        write!(f, "    {:<15} {:<20} {:<10} x{:<5} {:>5}   [{}]", 
+            self.rule, self.value, position, self.weight, score, seeks)
+    }
+}
+
+/// Number with at most one decimal
+fn rounded(value:f32) -> f32 {
+    (value * 10.0).round() / 10.0
+}
+
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CandidatePerson {
     pub qid:String,
     pub name:String,
     pub gender:String,
-    pub age:f32,
+    pub age:Option<f32>,
+
+    /// YYYY-MM-DD when known, `age` is computed from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub birthdate:Option<String>,
+
     pub education:String,
     pub verbal:String,
+    pub city:String,
+    pub profession:String,
+    pub seeking:String,
     pub email:String,
     pub phone:String,
 
-    pub match_score:f32
+    #[serde(default)]
+    pub languages:Vec<String>,
+
+    /// Out of 10, None if not rated yet
+    #[serde(default)]
+    pub response_rating:Option<f32>,
+
+    pub match_score:f32,
+
+    /// Filled by `Match::explain` only
+    #[serde(skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub explanation:Vec<ScoreDetail>,
+
+    /// How the candidate scored the person by their own policy, filled by `Match::explainMutual` only
+    #[serde(skip_deserializing, skip_serializing_if = "Vec::is_empty")]
+    pub their_explanation:Vec<ScoreDetail>
 }
 
 impl CandidatePerson {
@@ -28,15 +124,103 @@ impl CandidatePerson {
         Ordering::Equal
     }
 
+    /// Value of a profile field by its name in the database
+    pub fn field(&self, name:&str) -> Option<String> {
+        match name {
+            "qid" => Some(self.qid.clone()),
+            "name" => Some(self.name.clone()),
+            "gender" => Some(self.gender.clone()),
+            "age" => self.age.map(|age| age.to_string()),
+            "education" => Some(self.education.clone()),
+            "verbal_ability" | "verbal" => Some(self.verbal.clone()),
+            "city" => Some(self.city.clone()),
+            "profession" => Some(self.profession.clone()),
+            "seeking" => Some(self.seeking.clone()),
+            "email" => Some(self.email.clone()),
+            "phone" => Some(self.phone.clone()),
+            "languages" => Some(self.languages.join(", ")),
+            "response_rating" => self.response_rating.map(|rating| rating.to_string()),
+            _ => None
+        }
+    }
+
+    /// Values of a profile field, every entry for list fields like languages
+    pub fn values(&self, name:&str) -> Vec<String> {
+        match name {
+            "languages" => self.languages.clone(),
+            _ => self.field(name).into_iter().collect()
+        }
+    }
+
+    /// Numeric value of a profile field by its name in the database
+    pub fn field_f32(&self, name:&str) -> Option<f32> {
+        match name {
+            "age" => self.age,
+            _ => self.field(name)?.trim().parse::<f32>().ok()
+        }
+    }
+
+    /// Profile of the candidate, to match it the other way around
+    pub fn to_person(&self) -> DocPerson {
+        DocPerson {
+            qid:self.qid.clone(),
+            name:self.name.clone(),
+            gender:self.gender.clone(),
+            age:self.age.map(|age| age.to_string()).unwrap_or_default(),
+            email:self.email.clone(),
+            phone:self.phone.clone(),
+            city:self.city.clone(),
+            languages:self.languages.clone(),
+            profession:self.profession.clone(),
+            education:self.education.clone(),
+            response_rating:self.response_rating.map(|rating| rating.to_string()).unwrap_or_default(),
+            verbal_ability:self.verbal.clone(),
+            seeking:self.seeking.clone(),
+            birthdate:self.birthdate.clone(),
+        }
+    }
+
+    /// Age in whole years, "-" when unknown
+    fn display_age(&self) -> String {
+        match self.age {
+            Some(age) => (age as u32).to_string(),
+            None => String::from("-")
+        }
+    }
+
     pub fn print_detail(&self) {
         println!("{:<10} {:<40} {:<5} {:<10} {:<15} {:<15} {:<15} {:<20}", 
-        self.qid, self.name, self.age, self.gender, self.education, self.verbal, self.phone, self.email);
+        self.qid, self.name, self.display_age(), self.gender, self.education, self.verbal, self.phone, self.email);
+    }
+}
+
+impl From<&DocPerson> for CandidatePerson {
+    fn from(person:&DocPerson) -> Self {
+        CandidatePerson {
+            qid:person.qid.clone(),
+            name:person.name.clone(),
+            gender:person.gender.clone(),
+            age:person.field_f32("age"),
+            birthdate:person.birthdate.clone(),
+            education:person.education.clone(),
+            verbal:person.verbal_ability.clone(),
+            city:person.city.clone(),
+            profession:person.profession.clone(),
+            seeking:person.seeking.clone(),
+            email:person.email.clone(),
+            phone:person.phone.clone(),
+            languages:person.languages.clone(),
+            response_rating:person.rating(),
+            match_score:0.0,
+            explanation:Vec::new(),
+            their_explanation:Vec::new()
+        }
     }
 }
 
 impl fmt::Display for CandidatePerson {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:<10} {:<40} {:<5} {:<10} {:<15} {:<15} {:15}%", 
-            self.qid, self.name, self.age, self.gender, self.education, self.verbal,  (self.match_score as u8))
+            self.qid, self.name, self.display_age(), self.gender, self.education, self.verbal,  (self.match_score as u8))
     }
 }
\ No newline at end of file
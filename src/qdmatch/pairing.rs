@@ -0,0 +1,366 @@
+//  Pairing
+//  One to one pairing of all participants of an event, using the scores
+//  of `Match` as preference lists of both sides (Gale-Shapley).
+
+use std::collections::{HashMap, HashSet};
+use super::matcher::{Match, Lookups};
+use super::model::CandidatePerson;
+use super::rules::RuleBook;
+use crate::db::db_models::ExclusionList;
+
+
+/// Two persons paired together and how each one scored the other
+pub struct Pair {
+    pub first:CandidatePerson,
+    pub second:CandidatePerson,
+
+    /// Score of `second` by the policy of `first`
+    pub first_score:f32,
+
+    /// Score of `first` by the policy of `second`
+    pub second_score:f32,
+}
+
+/// Person who took no part in the pairing, and why
+pub struct Excluded {
+    pub person:CandidatePerson,
+    pub reason:&'static str,
+}
+
+/// Result of pairing an event
+pub struct Pairing {
+    pub pairs:Vec<Pair>,
+
+    /// Persons who took part but found nobody who accepts them back
+    pub unpaired:Vec<CandidatePerson>,
+    pub excluded:Vec<Excluded>,
+}
+
+
+/// Candidates of a person by qid, best first, with the score the person gave them
+pub(crate) type Preferences = HashMap<String, Vec<(String, f32)>>;
+
+pub(crate) fn preferences(persons:&Vec<CandidatePerson>, rules:&RuleBook, lookups:&Lookups, exclusions:&ExclusionList, seed:Option<u64>) -> Preferences {
+    let mut preferences:Preferences = HashMap::new();
+    let prior_matches = exclusions.met_counts();
+
+    for person in persons {
+        //Person without a policy doesn't seek anyone
+        let mut matcher = match Match::withRules(person.to_person(), rules, lookups) {
+            Ok(Some(matcher)) => matcher,
+            _ => continue
+        };
+        matcher.exclude(exclusions.excluded_for(&person.qid));
+        matcher.priorMatches(prior_matches.clone());
+        matcher.seed(seed);
+
+        let others:Vec<CandidatePerson> = persons.iter()
+            .filter(|other| other.qid != person.qid)
+            .map(|other| other.clone())
+            .collect();
+
+        let ranked = matcher.qurate(others).into_iter()
+            .map(|candidate| (candidate.qid, candidate.match_score))
+            .collect();
+        preferences.insert(person.qid.clone(), ranked);
+    }
+
+    preferences
+}
+
+/// Score `from` gave to `to`, None if `from` doesn't accept `to`
+pub(crate) fn score(preferences:&Preferences, from:&String, to:&String) -> Option<f32> {
+    preferences.get(from)?.iter()
+        .find(|(qid, _)| qid == to)
+        .map(|(_, score)| *score)
+}
+
+/// Position of `to` in the preference list of `from`, lower is better
+fn rank(preferences:&Preferences, from:&String, to:&String) -> Option<usize> {
+    preferences.get(from)?.iter().position(|(qid, _)| qid == to)
+}
+
+
+/// Stable pairing of all the persons. Persons of the gender `proposers` propose,
+/// everyone else receives, genders are compared by their canonical value.
+/// Two persons are only paired if both policies accept each other and neither
+/// is excluded for the other. Opted out persons and persons without a policy
+/// are reported as excluded. Proposers propose in qid order, `seed` is passed on to `Match` for ranking.
+///
+/// Persons seeking their own side can't be paired by proposals, the ones left over
+/// are paired among each other afterwards, the highest combined score first
+pub fn pair_event(persons:Vec<CandidatePerson>, rules:&RuleBook, lookups:&Lookups, exclusions:&ExclusionList, proposers:&str, seed:Option<u64>) -> Pairing {
+    let mut excluded:Vec<Excluded> = Vec::new();
+    let mut persons:Vec<CandidatePerson> = persons.into_iter()
+        .filter_map(|person| {
+            if exclusions.is_opted_out(&person.qid) {
+                excluded.push(Excluded { person, reason:"opted out" });
+                return None;
+            }
+            Some(person)
+        })
+        .collect();
+    persons.sort_by(|a, b| a.qid.cmp(&b.qid));
+    let preferences = preferences(&persons, rules, lookups, exclusions, seed);
+
+    let dictionary = &lookups.dictionary;
+    let proposers = dictionary.normalize("gender", proposers).to_lowercase();
+    let proposing:HashSet<&String> = persons.iter()
+        .filter(|person| dictionary.normalize("gender", &person.gender).to_lowercase() == proposers)
+        .map(|person| &person.qid)
+        .collect();
+
+    //Next candidate each proposer is going to propose to
+    let mut next:HashMap<String, usize> = HashMap::new();
+
+    //Receiver qid to the proposer qid it holds on to
+    let mut engaged:HashMap<String, String> = HashMap::new();
+
+    let mut free:Vec<String> = persons.iter()
+        .filter(|person| proposing.contains(&person.qid))
+        .map(|person| person.qid.clone())
+        .collect();
+    free.reverse();
+
+    while let Some(proposer) = free.pop() {
+        let list = match preferences.get(&proposer) {
+            Some(list) => list,
+            None => continue
+        };
+
+        //Propose to the next acceptable receiver
+        let index = next.entry(proposer.clone()).or_insert(0);
+        while *index < list.len() {
+            let receiver = &list[*index].0;
+            *index += 1;
+
+            if proposing.contains(receiver) {
+                continue;
+            }
+
+            //Receiver has to accept the proposer as well
+            let proposer_rank = match rank(&preferences, receiver, &proposer) {
+                Some(proposer_rank) => proposer_rank,
+                None => continue
+            };
+
+            match engaged.get(receiver) {
+                None => {
+                    engaged.insert(receiver.clone(), proposer.clone());
+                }
+
+                Some(current) => {
+                    let current_rank = rank(&preferences, receiver, current).unwrap_or(usize::MAX);
+                    if proposer_rank >= current_rank {
+                        continue;
+                    }
+
+                    //Receiver trades up, previous proposer is free again
+                    free.push(current.clone());
+                    engaged.insert(receiver.clone(), proposer.clone());
+                }
+            }
+
+            break;
+        }
+    }
+
+    //First person of each pair to the second, proposers first
+    let mut partner_of:HashMap<String, String> = engaged.iter()
+        .map(|(receiver, proposer)| (proposer.clone(), receiver.clone()))
+        .collect();
+
+    //Every free proposer was turned down by engaged receivers, so the persons
+    //left over who accept each other are on the same side
+    let left:Vec<&String> = persons.iter()
+        .map(|person| &person.qid)
+        .filter(|qid| preferences.contains_key(*qid) && !partner_of.contains_key(*qid) && !engaged.contains_key(*qid))
+        .collect();
+    let mut mutual:Vec<(f32, usize, usize)> = Vec::new();
+    for (first, a) in left.iter().enumerate() {
+        for (second, b) in left.iter().enumerate().skip(first + 1) {
+            if let (Some(a_score), Some(b_score)) = (score(&preferences, a, b), score(&preferences, b, a)) {
+                mutual.push((a_score + b_score, first, second));
+            }
+        }
+    }
+    mutual.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal).then((a.1, a.2).cmp(&(b.1, b.2))));
+
+    let mut taken:HashSet<usize> = HashSet::new();
+    for (_, first, second) in mutual {
+        if taken.contains(&first) || taken.contains(&second) {
+            continue;
+        }
+        taken.insert(first);
+        taken.insert(second);
+        partner_of.insert(left[first].clone(), left[second].clone());
+    }
+    let seconds:HashSet<&String> = partner_of.values().collect();
+
+    //Report pairs in the order of the persons
+    let mut pairs:Vec<Pair> = Vec::new();
+    let mut unpaired:Vec<CandidatePerson> = Vec::new();
+    let by_qid:HashMap<&String, &CandidatePerson> = persons.iter().map(|person| (&person.qid, person)).collect();
+
+    for person in &persons {
+        if let Some(partner) = partner_of.get(&person.qid) {
+            pairs.push(Pair {
+                first:person.clone(),
+                second:(*by_qid[partner]).clone(),
+                first_score:score(&preferences, &person.qid, partner).unwrap_or_default(),
+                second_score:score(&preferences, partner, &person.qid).unwrap_or_default(),
+            });
+        }else if !preferences.contains_key(&person.qid) {
+            excluded.push(Excluded { person:person.clone(), reason:"no policy applies" });
+        }else if !seconds.contains(&person.qid) {
+            unpaired.push(person.clone());
+        }
+    }
+    excluded.sort_by(|a, b| a.person.qid.cmp(&b.person.qid));
+
+    Pairing {
+        pairs,
+        unpaired,
+        excluded
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db_models::ExclusionKind;
+    use crate::qdmatch::testing::{person, ranking, exclusions};
+
+    fn qids(pairing:&Pairing) -> Vec<(&str, &str)> {
+        pairing.pairs.iter().map(|pair| (&pair.first.qid[..], &pair.second.qid[..])).collect()
+    }
+
+    /// No proposer and receiver who both prefer each other over the partner they got
+    fn assert_stable(pairing:&Pairing, preferences:&Preferences) {
+        let partner_of = |qid:&String| -> Option<&String> {
+            pairing.pairs.iter().find_map(|pair| {
+                if &pair.first.qid == qid { Some(&pair.second.qid) }
+                else if &pair.second.qid == qid { Some(&pair.first.qid) }
+                else { None }
+            })
+        };
+        let prefers = |from:&String, to:&String| -> bool {
+            match (rank(preferences, from, to), partner_of(from)) {
+                (Some(to_rank), Some(partner)) => to_rank < rank(preferences, from, partner).unwrap(),
+                (Some(_), None) => true,
+                _ => false
+            }
+        };
+
+        for pair in &pairing.pairs {
+            for other in &pairing.pairs {
+                if pair.first.qid != other.first.qid {
+                    assert!(!(prefers(&pair.first.qid, &other.second.qid) && prefers(&other.second.qid, &pair.first.qid)),
+                        "{} and {} block the pairing", pair.first.qid, other.second.qid);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pairing_is_stable() {
+        let persons = vec![
+            person("Q-1", "a", "Female"), person("Q-2", "b", "Female"), person("Q-3", "c", "Female"),
+            person("Q-4", "d", "Male"), person("Q-5", "e", "Male"), person("Q-6", "f", "Male"),
+        ];
+        let rules = ranking(&[
+            ("Q-1", &["d", "e", "f"]), ("Q-2", &["d", "f", "e"]), ("Q-3", &["e", "d", "f"]),
+            ("Q-4", &["b", "a", "c"]), ("Q-5", &["a", "c", "b"]), ("Q-6", &["a", "b", "c"]),
+        ]);
+        let none = exclusions(&[]);
+
+        let pairing = pair_event(persons.clone(), &rules, &Lookups::bundled(), &none, "Female", None);
+        assert_eq!(qids(&pairing), vec![("Q-1", "Q-5"), ("Q-2", "Q-4"), ("Q-3", "Q-6")]);
+        assert!(pairing.unpaired.is_empty());
+        assert_stable(&pairing, &preferences(&persons, &rules, &Lookups::bundled(), &none, None));
+    }
+
+    #[test]
+    fn larger_side_is_left_unpaired() {
+        let persons = vec![
+            person("Q-1", "a", "Female"), person("Q-2", "b", "Female"),
+            person("Q-3", "c", "Male"), person("Q-4", "d", "Male"), person("Q-5", "e", "Male"),
+        ];
+        let rules = ranking(&[
+            ("Q-1", &["c", "d", "e"]), ("Q-2", &["c", "e", "d"]),
+            ("Q-3", &["b", "a"]), ("Q-4", &["a", "b"]), ("Q-5", &["a", "b"]),
+        ]);
+        let none = exclusions(&[]);
+
+        let pairing = pair_event(persons.clone(), &rules, &Lookups::bundled(), &none, "Female", None);
+        assert_eq!(qids(&pairing), vec![("Q-1", "Q-4"), ("Q-2", "Q-3")]);
+        let unpaired:Vec<&str> = pairing.unpaired.iter().map(|person| &person.qid[..]).collect();
+        assert_eq!(unpaired, vec!["Q-5"]);
+        assert_stable(&pairing, &preferences(&persons, &rules, &Lookups::bundled(), &none, None));
+    }
+
+    #[test]
+    fn persons_nobody_accepts_stay_unpaired() {
+        let persons = vec![
+            person("Q-1", "a", "Female"), person("Q-2", "b", "Female"),
+            person("Q-3", "c", "Male"), person("Q-4", "d", "Male"),
+        ];
+        let rules = ranking(&[
+            ("Q-1", &["c"]), ("Q-2", &["c"]),
+            ("Q-3", &["a", "b"]), ("Q-4", &["a", "b"]),
+        ]);
+
+        let pairing = pair_event(persons, &rules, &Lookups::bundled(), &exclusions(&[]), "Female", None);
+        assert_eq!(qids(&pairing), vec![("Q-1", "Q-3")]);
+        let unpaired:Vec<&str> = pairing.unpaired.iter().map(|person| &person.qid[..]).collect();
+        assert_eq!(unpaired, vec!["Q-2", "Q-4"]);
+    }
+
+    #[test]
+    fn genders_are_compared_by_canonical_value() {
+        let persons = vec![person("Q-1", "a", "F"), person("Q-2", "b", "male")];
+        let rules = ranking(&[("Q-1", &["b"]), ("Q-2", &["a"])]);
+
+        let pairing = pair_event(persons, &rules, &Lookups::bundled(), &exclusions(&[]), "female", None);
+        assert_eq!(qids(&pairing), vec![("Q-1", "Q-2")]);
+    }
+
+    #[test]
+    fn left_out_persons_are_reported() {
+        let persons = vec![
+            person("Q-1", "a", "Female"), person("Q-2", "b", "Male"),
+            person("Q-3", "c", "Nonbinary"), person("Q-4", "d", "Male"),
+        ];
+        let rules = ranking(&[("Q-1", &["b", "d"]), ("Q-2", &["a"]), ("Q-4", &["a"])]);
+        let list = exclusions(&[(ExclusionKind::OptedOut, "Q-4", None)]);
+
+        let pairing = pair_event(persons, &rules, &Lookups::bundled(), &list, "Female", None);
+        assert_eq!(qids(&pairing), vec![("Q-1", "Q-2")]);
+        let excluded:Vec<(&str, &str)> = pairing.excluded.iter().map(|excluded| (&excluded.person.qid[..], excluded.reason)).collect();
+        assert_eq!(excluded, vec![("Q-3", "no policy applies"), ("Q-4", "opted out")]);
+    }
+
+    #[test]
+    fn persons_seeking_their_own_side_are_paired() {
+        let seeking = |qid, name, gender:&str, seeks:&str| {
+            let mut person = person(qid, name, gender);
+            person.seeking = String::from(seeks);
+            person
+        };
+        let persons = vec![
+            person("Q-1", "a", "Female"), seeking("Q-2", "b", "Female", "Female"), seeking("Q-3", "c", "Female", "Female"),
+            seeking("Q-4", "d", "Female", "Female"), person("Q-5", "e", "Male"),
+            seeking("Q-6", "f", "Male", "Male"), seeking("Q-7", "g", "Male", "Male"),
+        ];
+        let rules = ranking(&[
+            ("Q-1", &["e"]), ("Q-2", &["d", "c"]), ("Q-3", &["b", "d"]), ("Q-4", &["b", "c"]),
+            ("Q-5", &["a"]), ("Q-6", &["g"]), ("Q-7", &["f"]),
+        ]);
+
+        let pairing = pair_event(persons, &rules, &Lookups::bundled(), &exclusions(&[]), "Female", None);
+        assert_eq!(qids(&pairing), vec![("Q-1", "Q-5"), ("Q-2", "Q-4"), ("Q-6", "Q-7")]);
+        let unpaired:Vec<&str> = pairing.unpaired.iter().map(|person| &person.qid[..]).collect();
+        assert_eq!(unpaired, vec!["Q-3"]);
+    }
+}
@@ -0,0 +1,422 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use super::dictionary::{Synonyms, normalize};
+
+
+/// How the seek list of a rule is compared against the candidate's value
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleKind {
+    /// String list in priority order, earlier entries score higher
+    Ordered,
+
+    /// Float list of offsets added to the seeker's own value, in priority order
+    Offset,
+
+    /// String list where every entry scores the same
+    Set,
+
+    /// Seeker's own values of a list field in priority order, like languages.
+    /// Candidates score by the best ranked value they share, `rule_data` is not used
+    Overlap,
+
+    /// String list with the compatibility of each entry out of 100 in `r_seek_float`
+    Matrix,
+
+    /// Cities by distance in km from the seeker's city, `r_tolerance` gives the radius
+    Distance,
+}
+
+fn default_rule_kind() -> RuleKind {
+    RuleKind::Ordered
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+
+/// How the scores of the rules of a policy are combined into the match score
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// Mean of the scores by rule weight
+    WeightedMean,
+
+    /// Geometric mean of the scores by rule weight, a single zero makes the score zero
+    GeometricMean,
+
+    /// Rank by the score of the first rule, then the next one on a tie and so on.
+    /// Match score is the weighted mean
+    Lexicographic,
+}
+
+impl Default for Aggregation {
+    fn default() -> Self {
+        Aggregation::WeightedMean
+    }
+}
+
+
+/// How fast the score drops outside the preferred band of a tolerance
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Curve {
+    /// Straight down to zero at `falloff` away from the band
+    Linear,
+
+    /// Bell curve with `falloff` as deviation, zero beyond three times of it
+    Gaussian,
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Curve::Linear
+    }
+}
+
+/// Numeric matching by range instead of exact values
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct Tolerance {
+    /// Lowest and highest offset to the person's own value which score full
+    pub band:(f32, f32),
+
+    /// Distance outside the band over which the score decays
+    #[serde(default)]
+    pub falloff:f32,
+
+    #[serde(default)]
+    pub curve:Curve,
+}
+
+
+/// Row of a rule for persons without a row of their own
+pub const ANY_ROW:&'static str = "*";
+
+/// One row of a rule, what a person with `r_self` seeks in a partner
+#[derive(Debug, Deserialize)]
+pub struct RuleData {
+    /// Value of the seeker this row is for, `*` for anyone
+    pub r_self:String,
+
+    #[serde(default)]
+    pub r_seek_string:Vec<String>,
+
+    #[serde(default)]
+    pub r_seek_float:Vec<f32>,
+
+    /// Offset and distance rules only, replaces `r_seek_float` when given
+    #[serde(default)]
+    pub r_tolerance:Option<Tolerance>,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct Rule {
+    pub rule_name:String,
+
+    #[serde(default)]
+    pub rule_description:String,
+
+    pub rule_id:i32,
+
+    /// Profile field of the candidate this rule is scored against
+    pub rule_field:String,
+
+    /// Profile field of the seeker used to pick the row from `rule_data`,
+    /// same as `rule_field` when not given
+    #[serde(default)]
+    pub rule_self_field:Option<String>,
+
+    #[serde(default = "default_rule_kind")]
+    pub rule_kind:RuleKind,
+
+    /// Share of this rule in the match score
+    #[serde(default = "default_weight")]
+    pub rule_weight:f32,
+
+    /// Policy can't be applied to a person for whom this rule has no row
+    #[serde(default)]
+    pub rule_required:bool,
+
+    /// Candidates not in the seek list are dropped, already in the database query
+    #[serde(default)]
+    pub rule_cut:bool,
+
+    #[serde(default)]
+    pub rule_data:Vec<RuleData>,
+}
+
+impl Rule {
+    pub fn self_field(&self) -> &str {
+        match &self.rule_self_field {
+            Some(field) => field,
+            None => &self.rule_field
+        }
+    }
+
+    /// Row of the rule for the given value of the seeker, rows are compared by canonical value
+    pub fn data_for(&self, self_value:&str, synonyms:&[Synonyms]) -> Option<&RuleData> {
+        let self_value = normalize(synonyms, self_value).to_lowercase();
+        self.rule_data.iter().find(|node| normalize(synonyms, &node.r_self).to_lowercase() == self_value)
+    }
+}
+
+
+/// Group of free text values of a field, like all the engineering professions
+#[derive(Debug, Deserialize, Clone)]
+pub struct Category {
+    pub c_name:String,
+
+    /// Words or phrases of a value which put it in this category, without case
+    #[serde(default)]
+    pub c_keywords:Vec<String>,
+}
+
+/// Lowercase words of a text separated by single spaces, with a space on both ends
+fn words(text:&str) -> String {
+    let words:Vec<String> = text.to_lowercase()
+        .split(|c:char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_string())
+        .collect();
+    format!(" {} ", words.join(" "))
+}
+
+impl Category {
+    /// Whether the value is the category name or holds one of the keywords
+    pub fn contains(&self, value:&str) -> bool {
+        let value = words(value);
+        if value == words(&self.c_name) {
+            return true;
+        }
+
+        self.c_keywords.iter().any(|keyword| {
+            let keyword = words(keyword);
+            keyword.trim().len() > 0 && value.contains(&keyword)
+        })
+    }
+}
+
+/// Lowercase name of the first category holding the value, the value itself if there is none
+pub fn categorize(categories:&[Category], value:&str) -> String {
+    match categories.iter().find(|category| category.contains(value)) {
+        Some(category) => category.c_name.trim().to_lowercase(),
+        None => value.trim().to_lowercase()
+    }
+}
+
+
+fn default_neutral() -> f32 {
+    5.0
+}
+
+/// Change of the match score by the response rating of the candidate
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct ResponseBoost {
+    /// Rating out of 10 which neither raises nor lowers the score
+    #[serde(default = "default_neutral")]
+    pub neutral:f32,
+
+    /// Points added for a rating of 10, less for ratings closer to neutral
+    #[serde(default)]
+    pub boost:f32,
+
+    /// Points taken off for a rating of 0, less for ratings closer to neutral
+    #[serde(default)]
+    pub penalty:f32,
+}
+
+impl ResponseBoost {
+    /// Points to add to the match score for the rating, negative for a penalty
+    pub fn adjustment(&self, rating:f32) -> f32 {
+        let neutral = self.neutral.max(0.0).min(10.0);
+        if rating >= neutral {
+            if neutral >= 10.0 {
+                return 0.0;
+            }
+            return self.boost * (rating - neutral) / (10.0 - neutral);
+        }
+
+        self.penalty * (rating - neutral) / neutral
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct Policy {
+    pub p_self:String,
+    pub p_partner:String,
+    pub p_rules:Vec<i32>,
+
+    #[serde(default)]
+    pub p_aggregation:Aggregation,
+}
+
+
+/// Content of the rules file
+#[derive(Debug, Deserialize)]
+pub struct MatchRule {
+    pub rules:Vec<Rule>,
+    pub policy:Vec<Policy>,
+
+    /// Categories by field name, values of these fields are compared by category
+    #[serde(default)]
+    pub categories:BTreeMap<String, Vec<Category>>,
+
+    /// Candidates who are not rated yet are scored as they are
+    #[serde(default)]
+    pub response_rating:Option<ResponseBoost>,
+}
+
+impl MatchRule {
+    pub fn categories_for(&self, field:&str) -> &[Category] {
+        match self.categories.get(field) {
+            Some(categories) => categories,
+            None => &[]
+        }
+    }
+
+    pub fn rule(&self, rule_id:i32) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.rule_id == rule_id)
+    }
+}
+
+
+
+/// Whether a role of a `qd_rules.json` policy drops candidates or only scores them
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoleType {
+    /// Candidates not in the list are dropped, the rest are scored by priority
+    Cut,
+
+    /// Candidates are only scored by priority
+    Weighted,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum ListType {
+    /// One list of values for every person
+    #[serde(rename = "1d")]
+    OneD,
+
+    /// One list per value of the person's own field
+    #[serde(rename = "2d")]
+    TwoD,
+}
+
+fn default_gender() -> String {
+    "-".to_string()
+}
+
+fn default_1d_list() -> ListType {
+    ListType::OneD
+}
+
+
+/// Row of a 2d list, what a person with `self` seeks in a partner
+#[derive(Debug, Deserialize)]
+pub struct QdListRow {
+    #[serde(rename = "self")]
+    pub r_self:String,
+    pub partner:Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum QdList {
+    /// Values in priority order, `"same"` stands for the person's own value
+    Text(Vec<String>),
+
+    /// Offsets to the person's own value in priority order
+    Number(Vec<f32>),
+
+    Table(Vec<QdListRow>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QdRole {
+    #[serde(rename = "type")]
+    pub role_type:RoleType,
+
+    #[serde(default = "default_1d_list")]
+    pub list_type:ListType,
+
+    pub list:QdList,
+
+    /// Numeric lists only, replaces the list when given
+    #[serde(default)]
+    pub tolerance:Option<Tolerance>,
+
+    /// Share of this role in the match score
+    #[serde(default = "default_weight")]
+    pub weight:f32,
+}
+
+/// Policy of `qd_rules.json`, every key other than the ones below is a role
+/// named after the profile field it applies to
+#[derive(Debug, Deserialize)]
+pub struct QdPolicy {
+    #[serde(default = "default_gender")]
+    pub first:String,
+
+    #[serde(default = "default_gender")]
+    pub second:String,
+
+    #[serde(default)]
+    pub role_priority:Vec<String>,
+
+    #[serde(default)]
+    pub aggregation:Aggregation,
+
+    /// Raise or lower scores by the response rating of candidates, like `response_rating`
+    /// of `rules.json`. Named apart from it, a `response_rating` key here is a role
+    #[serde(default)]
+    pub response_boost:Option<ResponseBoost>,
+
+    #[serde(flatten)]
+    pub roles:BTreeMap<String, QdRole>,
+}
+
+impl QdPolicy {
+    /// Roles in evaluation order, the ones in `role_priority` first
+    pub fn ordered_roles(&self) -> Vec<(&String, &QdRole)> {
+        let mut roles:Vec<(&String, &QdRole)> = Vec::new();
+        for name in &self.role_priority {
+            if let Some((key, role)) = self.roles.get_key_value(name) {
+                roles.push((key, role));
+            }
+        }
+
+        for (name, role) in &self.roles {
+            if !self.role_priority.contains(name) {
+                roles.push((name, role));
+            }
+        }
+
+        roles
+    }
+}
+
+
+
+/// Any of the supported rule file formats
+#[derive(Debug)]
+pub enum RuleBook {
+    /// `rules.json`, an object with rules and policies
+    Rules(MatchRule),
+
+    /// `qd_rules.json`, a list of policies
+    QdRules(Vec<QdPolicy>),
+}
+
+impl RuleBook {
+    pub fn from_json(json:serde_json::Value) -> serde_json::Result<Self> {
+        if json.is_array() {
+            return Ok(RuleBook::QdRules(serde_json::from_value(json)?));
+        }
+
+        Ok(RuleBook::Rules(serde_json::from_value(json)?))
+    }
+}
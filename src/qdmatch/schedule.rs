@@ -0,0 +1,259 @@
+//  Schedule
+//  Rotation of speed dating rounds, every round seats mutually interested
+//  pairs at the tables, no pair meets twice.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::collections::{HashMap, HashSet};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use super::matcher::{harmonic_mean, Lookups};
+use super::model::CandidatePerson;
+use super::pairing::{preferences, score};
+use super::rules::RuleBook;
+use crate::db::db_models::ExclusionList;
+
+
+/// A date at a table in a round
+pub struct Seat {
+    pub table:usize,
+    pub first:CandidatePerson,
+    pub second:CandidatePerson,
+
+    /// Score of the pair, both directions combined
+    pub score:f32,
+}
+
+pub struct Round {
+    pub number:usize,
+    pub seats:Vec<Seat>,
+
+    /// Participants without a date this round
+    pub resting:Vec<CandidatePerson>,
+}
+
+pub struct Schedule {
+    pub tables:usize,
+    pub rounds:Vec<Round>,
+
+    /// Seed the schedule was built with, recorded to build it again
+    pub seed:Option<u64>,
+}
+
+
+/// Pair which could meet, by index in the participants
+struct Candidate {
+    first:usize,
+    second:usize,
+    score:f32,
+}
+
+/// Every pair where both policies accept each other
+fn candidates(persons:&Vec<CandidatePerson>, rules:&RuleBook, lookups:&Lookups, exclusions:&ExclusionList, seed:Option<u64>) -> Vec<Candidate> {
+    let preferences = preferences(persons, rules, lookups, exclusions, seed);
+    let mut candidates:Vec<Candidate> = Vec::new();
+
+    for first in 0..persons.len() {
+        for second in (first + 1)..persons.len() {
+            let first_score = score(&preferences, &persons[first].qid, &persons[second].qid);
+            let second_score = score(&preferences, &persons[second].qid, &persons[first].qid);
+
+            if let (Some(first_score), Some(second_score)) = (first_score, second_score) {
+                candidates.push(Candidate {
+                    first,
+                    second,
+                    score:harmonic_mean(first_score, second_score)
+                });
+            }
+        }
+    }
+
+    candidates
+}
+
+
+/// Build `rounds` rounds with at most `tables` dates each.
+/// Participants with fewer dates so far are seated first, then higher scoring pairs.
+/// Rounds start at a different table so every table gets used evenly.
+/// Pairs which already met or blocked each other never meet, opted out persons are left out.
+/// Pairs of equal score are taken in qid order, unless a `seed` shuffles them.
+pub fn schedule(persons:Vec<CandidatePerson>, rules:&RuleBook, lookups:&Lookups, exclusions:&ExclusionList, rounds:usize, tables:usize, seed:Option<u64>) -> Schedule {
+    let mut persons:Vec<CandidatePerson> = persons.into_iter()
+        .filter(|person| !exclusions.is_opted_out(&person.qid))
+        .collect();
+    persons.sort_by(|a, b| a.qid.cmp(&b.qid));
+
+    let mut candidates = candidates(&persons, rules, lookups, exclusions, seed);
+    if let Some(seed) = seed {
+        candidates.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+    candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut met:HashSet<(usize, usize)> = HashSet::new();
+    let mut dates:HashMap<usize, usize> = HashMap::new();
+    let mut schedule = Schedule {
+        tables,
+        rounds:Vec::new(),
+        seed
+    };
+
+    for number in 0..rounds {
+        //Stable sort keeps the better score first among equally busy pairs
+        let mut order:Vec<&Candidate> = candidates.iter()
+            .filter(|candidate| !met.contains(&(candidate.first, candidate.second)))
+            .collect();
+        order.sort_by_key(|candidate| {
+            dates.get(&candidate.first).unwrap_or(&0) + dates.get(&candidate.second).unwrap_or(&0)
+        });
+
+        let mut seated:HashSet<usize> = HashSet::new();
+        let mut seats:Vec<Seat> = Vec::new();
+        for candidate in order {
+            if seats.len() >= tables {
+                break;
+            }
+
+            if seated.contains(&candidate.first) || seated.contains(&candidate.second) {
+                continue;
+            }
+
+            seated.insert(candidate.first);
+            seated.insert(candidate.second);
+            met.insert((candidate.first, candidate.second));
+            *dates.entry(candidate.first).or_insert(0) += 1;
+            *dates.entry(candidate.second).or_insert(0) += 1;
+
+            seats.push(Seat {
+                table:(seats.len() + number) % tables + 1,
+                first:persons[candidate.first].clone(),
+                second:persons[candidate.second].clone(),
+                score:candidate.score
+            });
+        }
+
+        seats.sort_by_key(|seat| seat.table);
+        let resting = (0..persons.len())
+            .filter(|index| !seated.contains(index))
+            .map(|index| persons[index].clone())
+            .collect();
+
+        schedule.rounds.push(Round {
+            number:number + 1,
+            seats,
+            resting
+        });
+    }
+
+    schedule
+}
+
+
+impl Schedule {
+
+    /// Write the schedule as csv, one line per date
+    pub fn export(&self, filename:&str) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        writeln!(file, "round,table,first_qid,first_name,second_qid,second_name,score,seed")?;
+        let seed = self.seed.map(|seed| seed.to_string()).unwrap_or_default();
+
+        for round in &self.rounds {
+            for seat in &round.seats {
+                writeln!(file, "{},{},{},\"{}\",{},\"{}\",{},{}",
+                    round.number, seat.table,
+                    seat.first.qid, seat.first.name.replace("\"", "\"\""),
+                    seat.second.qid, seat.second.name.replace("\"", "\"\""),
+                    (seat.score as u8), seed)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(seed) = self.seed {
+            writeln!(f, "Seed : {}", seed)?;
+        }
+
+        for round in &self.rounds {
+            writeln!(f, "- Round {} [{} of {} tables]", round.number, round.seats.len(), self.tables)?;
+            for seat in &round.seats {
+                writeln!(f, "{:<5} {:<10} {:<40} {:<10} {:<40} {:>4}%",
+                    seat.table, seat.first.qid, seat.first.name, seat.second.qid, seat.second.name, (seat.score as u8))?;
+            }
+
+            if round.resting.len() > 0 {
+                let resting:Vec<&str> = round.resting.iter().map(|person| &person.qid[..]).collect();
+                writeln!(f, "Resting : {}", resting.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db_models::ExclusionKind;
+    use crate::qdmatch::testing::{person, ranking, exclusions};
+
+    fn event() -> Vec<CandidatePerson> {
+        vec![
+            person("Q-1", "a", "Female"), person("Q-2", "b", "Female"), person("Q-3", "c", "Female"), person("Q-4", "d", "Female"),
+            person("Q-5", "e", "Male"), person("Q-6", "f", "Male"), person("Q-7", "g", "Male"), person("Q-8", "h", "Male"),
+        ]
+    }
+
+    /// Qids of every date, round by round
+    fn dates(schedule:&Schedule) -> Vec<Vec<(usize, String, String)>> {
+        schedule.rounds.iter()
+            .map(|round| round.seats.iter().map(|seat| (seat.table, seat.first.qid.clone(), seat.second.qid.clone())).collect())
+            .collect()
+    }
+
+    #[test]
+    fn nobody_meets_twice() {
+        let met = exclusions(&[(ExclusionKind::Met, "Q-1", Some("Q-5"))]);
+        let schedule = schedule(event(), &ranking(&[]), &Lookups::bundled(), &met, 6, 3, None);
+
+        let mut pairs:HashSet<(String, String)> = HashSet::new();
+        for round in &schedule.rounds {
+            let mut seated:HashSet<&String> = HashSet::new();
+            for seat in &round.seats {
+                assert!(seated.insert(&seat.first.qid) && seated.insert(&seat.second.qid), "seated twice in round {}", round.number);
+                assert!(pairs.insert((seat.first.qid.clone(), seat.second.qid.clone())), "{} met {} again", seat.first.qid, seat.second.qid);
+            }
+        }
+
+        assert!(!pairs.contains(&(String::from("Q-1"), String::from("Q-5"))));
+        assert_eq!(pairs.len(), 15);
+    }
+
+    #[test]
+    fn tables_are_in_range() {
+        let schedule = schedule(event(), &ranking(&[]), &Lookups::bundled(), &exclusions(&[]), 4, 3, None);
+
+        for round in &schedule.rounds {
+            let tables:HashSet<usize> = round.seats.iter().map(|seat| seat.table).collect();
+            assert_eq!(tables.len(), round.seats.len());
+            assert!(tables.iter().all(|table| *table >= 1 && *table <= 3));
+            assert_eq!(round.resting.len(), 8 - 2 * round.seats.len());
+        }
+        assert_eq!(schedule.rounds[0].seats.len(), 3);
+    }
+
+    #[test]
+    fn same_seed_gives_same_schedule() {
+        let first = schedule(event(), &ranking(&[]), &Lookups::bundled(), &exclusions(&[]), 4, 3, Some(42));
+        let second = schedule(event().into_iter().rev().collect(), &ranking(&[]), &Lookups::bundled(), &exclusions(&[]), 4, 3, Some(42));
+        assert_eq!(dates(&first), dates(&second));
+
+        let unseeded = schedule(event(), &ranking(&[]), &Lookups::bundled(), &exclusions(&[]), 4, 3, None);
+        assert_eq!(dates(&unseeded), dates(&schedule(event(), &ranking(&[]), &Lookups::bundled(), &exclusions(&[]), 4, 3, None)));
+    }
+}
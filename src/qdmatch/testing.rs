@@ -0,0 +1,66 @@
+//  Testing
+//  Persons and rules shared by the tests of the matching modules.
+
+use serde_json::json;
+use super::model::CandidatePerson;
+use super::rules::RuleBook;
+use crate::db::db_models::{DocExclusion, ExclusionKind, ExclusionList};
+
+
+pub(crate) fn person(qid:&str, name:&str, gender:&str) -> CandidatePerson {
+    CandidatePerson {
+        qid:String::from(qid),
+        name:String::from(name),
+        gender:String::from(gender),
+        age:None,
+        birthdate:None,
+        education:String::new(),
+        verbal:String::new(),
+        city:String::new(),
+        profession:String::new(),
+        seeking:String::new(),
+        email:String::new(),
+        phone:String::new(),
+        languages:Vec::new(),
+        response_rating:None,
+        match_score:0.0,
+        explanation:Vec::new(),
+        their_explanation:Vec::new()
+    }
+}
+
+/// Rules where every person ranks others by name, best first. Only the
+/// ranked names are accepted, persons without a row accept everyone alike.
+/// Persons seeking their own gender get a policy too
+pub(crate) fn ranking(rows:&[(&str, &[&str])]) -> RuleBook {
+    let data:Vec<serde_json::Value> = rows.iter()
+        .map(|(qid, names)| json!({"r_self":qid, "r_seek_string":names}))
+        .collect();
+
+    RuleBook::from_json(json!({
+        "rules":[{
+            "rule_name":"ranking",
+            "rule_id":1,
+            "rule_field":"name",
+            "rule_self_field":"qid",
+            "rule_cut":true,
+            "rule_data":data
+        }],
+        "policy":[
+            {"p_self":"Female", "p_partner":"Male", "p_rules":[1]},
+            {"p_self":"Male", "p_partner":"Female", "p_rules":[1]},
+            {"p_self":"Female", "p_partner":"Female", "p_rules":[1]},
+            {"p_self":"Male", "p_partner":"Male", "p_rules":[1]}
+        ]
+    })).unwrap()
+}
+
+pub(crate) fn exclusions(list:&[(ExclusionKind, &str, Option<&str>)]) -> ExclusionList {
+    ExclusionList::new(list.iter()
+        .map(|(kind, qid, other)| DocExclusion {
+            kind:*kind,
+            qid:String::from(*qid),
+            other:other.map(String::from)
+        })
+        .collect())
+}